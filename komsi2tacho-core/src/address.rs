@@ -0,0 +1,239 @@
+//! J1939 NAME and address claim (J1939-81).
+//!
+//! We claim our preferred address (0xEE, the tachograph) at startup. If another ECU with a
+//! higher priority NAME claims the same address, we move to a free address of the
+//! self-configurable range or, if that is not possible, stop sending ("cannot claim").
+
+use crate::frame::CanFrame;
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant};
+
+pub const PGN_ADDRESS_CLAIMED: u32 = 60928;
+pub const PGN_REQUEST: u32 = 59904;
+
+/// The address of the MTCO 1324 tachograph
+pub const DEFAULT_ADDRESS: u8 = 0xEE;
+pub const NULL_ADDRESS: u8 = 0xFE;
+pub const GLOBAL_ADDRESS: u8 = 0xFF;
+
+// addresses we may take if the preferred one is taken
+const SELF_CONFIGURABLE: core::ops::RangeInclusive<u8> = 128..=247;
+
+// after a claim we must wait this long before we send anything else
+const CLAIM_DELAY: Duration = Duration::from_millis(250);
+
+/// The 64 bit J1939 NAME. The lower the value, the higher the priority in a conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Name {
+    /// 21 bit, unique per device, see [`Name::with_identity_number`]
+    pub identity_number: u32,
+    /// 11 bit
+    pub manufacturer_code: u16,
+    /// 3 bit
+    pub ecu_instance: u8,
+    /// 5 bit
+    pub function_instance: u8,
+    pub function: u8,
+    /// 7 bit
+    pub vehicle_system: u8,
+    /// 4 bit
+    pub vehicle_system_instance: u8,
+    /// 3 bit
+    pub industry_group: u8,
+    pub arbitrary_address_capable: bool,
+}
+
+impl Name {
+    /// Our NAME with identity number 0, the adapters set the one of the device
+    pub const fn new() -> Self {
+        Self {
+            identity_number: 0,
+            manufacturer_code: 0,
+            ecu_instance: 0,
+            function_instance: 0,
+            function: 20, // trip recorder, the closest to a tachograph
+            vehicle_system: 0,
+            vehicle_system_instance: 0,
+            industry_group: 1, // on-highway
+            arbitrary_address_capable: true,
+        }
+    }
+
+    /// Two devices on one bus must not have the same NAME, so the firmware takes the number
+    /// from the MAC address of the chip (see [`identity_number_from_mac`]).
+    pub const fn with_identity_number(mut self, identity_number: u32) -> Self {
+        self.identity_number = identity_number & 0x1F_FFFF;
+        self
+    }
+
+    pub const fn to_u64(&self) -> u64 {
+        (self.identity_number as u64 & 0x1F_FFFF)
+            | (self.manufacturer_code as u64 & 0x7FF) << 21
+            | (self.ecu_instance as u64 & 0x07) << 32
+            | (self.function_instance as u64 & 0x1F) << 35
+            | (self.function as u64) << 40
+            | (self.vehicle_system as u64 & 0x7F) << 49
+            | (self.vehicle_system_instance as u64 & 0x0F) << 56
+            | (self.industry_group as u64 & 0x07) << 60
+            | (self.arbitrary_address_capable as u64) << 63
+    }
+
+    pub const fn from_u64(v: u64) -> Self {
+        Self {
+            identity_number: (v & 0x1F_FFFF) as u32,
+            manufacturer_code: ((v >> 21) & 0x7FF) as u16,
+            ecu_instance: ((v >> 32) & 0x07) as u8,
+            function_instance: ((v >> 35) & 0x1F) as u8,
+            function: (v >> 40) as u8,
+            vehicle_system: ((v >> 49) & 0x7F) as u8,
+            vehicle_system_instance: ((v >> 56) & 0x0F) as u8,
+            industry_group: ((v >> 60) & 0x07) as u8,
+            arbitrary_address_capable: v >> 63 != 0,
+        }
+    }
+}
+
+/// The lower 21 bits of the device specific half of a MAC address
+pub const fn identity_number_from_mac(mac: [u8; 6]) -> u32 {
+    u32::from_be_bytes([0, mac[3], mac[4], mac[5]]) & 0x1F_FFFF
+}
+
+impl Default for Name {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Address Claimed (or Cannot Claim with the null address) to everybody
+pub fn build_address_claimed_frame(name: &Name, address: u8) -> CanFrame {
+    // priority 6, PDU1 with destination global
+    let id = 6 << 26 | PGN_ADDRESS_CLAIMED << 8 | (GLOBAL_ADDRESS as u32) << 8 | address as u32;
+    CanFrame::new(id, &name.to_u64().to_le_bytes())
+}
+
+/// State of our address claim, without any I/O so it can be tested
+#[derive(Debug, Clone)]
+pub struct AddressClaim {
+    name: Name,
+    preferred: u8,
+    /// None = we could not claim any address
+    address: Option<u8>,
+    claimed_at: Option<Instant>,
+    // addresses claimed by other ECUs, one bit per address
+    used: [u32; 8],
+}
+
+impl AddressClaim {
+    pub const fn new(name: Name, preferred: u8) -> Self {
+        Self {
+            name,
+            preferred,
+            address: Some(preferred),
+            claimed_at: None,
+            used: [0; 8],
+        }
+    }
+
+    pub fn name(&self) -> Name {
+        self.name
+    }
+
+    pub fn preferred(&self) -> u8 {
+        self.preferred
+    }
+
+    /// Our address, None if we lost it and found no other one
+    pub fn address(&self) -> Option<u8> {
+        self.address
+    }
+
+    /// Starts over with the preferred address and returns the claim to send
+    pub fn start(&mut self, now: Instant) -> CanFrame {
+        self.address = Some(self.preferred);
+        self.claimed_at = Some(now);
+        self.used = [0; 8];
+        self.claim_frame()
+    }
+
+    /// True if we own an address and the claim delay is over
+    pub fn may_send(&self, now: Instant) -> bool {
+        match (self.address, self.claimed_at) {
+            (Some(_), Some(at)) => now.saturating_duration_since(at) >= CLAIM_DELAY,
+            _ => false,
+        }
+    }
+
+    /// Our answer to a Request for Address Claimed
+    pub fn claim_frame(&self) -> CanFrame {
+        build_address_claimed_frame(&self.name, self.address.unwrap_or(NULL_ADDRESS))
+    }
+
+    /// Another ECU claimed an address. Returns the frame we have to send in reply.
+    pub fn on_address_claimed(
+        &mut self,
+        address: u8,
+        name: Name,
+        now: Instant,
+    ) -> Option<CanFrame> {
+        if address != NULL_ADDRESS {
+            self.mark_used(address);
+        }
+        if Some(address) != self.address || name == self.name {
+            return None;
+        }
+
+        if self.name.to_u64() < name.to_u64() {
+            // we win, the other ECU has to move
+            return Some(self.claim_frame());
+        }
+
+        // we lose
+        self.address = if self.name.arbitrary_address_capable {
+            self.free_address()
+        } else {
+            None
+        };
+        self.claimed_at = Some(now);
+        Some(self.claim_frame())
+    }
+
+    fn mark_used(&mut self, address: u8) {
+        self.used[address as usize / 32] |= 1 << (address % 32);
+    }
+
+    fn is_used(&self, address: u8) -> bool {
+        self.used[address as usize / 32] & (1 << (address % 32)) != 0
+    }
+
+    fn free_address(&self) -> Option<u8> {
+        SELF_CONFIGURABLE.clone().find(|a| !self.is_used(*a))
+    }
+}
+
+static ADDRESS_CLAIM: Mutex<CriticalSectionRawMutex, RefCell<AddressClaim>> = Mutex::new(
+    RefCell::new(AddressClaim::new(Name::new(), DEFAULT_ADDRESS)),
+);
+
+/// Sets NAME and preferred address, must be called before the CAN tasks start
+pub fn configure(name: Name, preferred: u8) {
+    ADDRESS_CLAIM.lock(|c| *c.borrow_mut() = AddressClaim::new(name, preferred));
+}
+
+/// Runs `f` with the address claim state
+pub fn with_claim<R>(f: impl FnOnce(&mut AddressClaim) -> R) -> R {
+    ADDRESS_CLAIM.lock(|c| f(&mut c.borrow_mut()))
+}
+
+/// Source address for our frames (the null address if we have none)
+pub fn source_address() -> u8 {
+    with_claim(|c| c.address().unwrap_or(NULL_ADDRESS))
+}
+
+/// False while the claim is running or if we have no address
+pub fn may_send() -> bool {
+    let now = Instant::now();
+    with_claim(|c| c.may_send(now))
+}
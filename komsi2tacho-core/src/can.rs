@@ -0,0 +1,529 @@
+use crate::address::{self, Name, PGN_ADDRESS_CLAIMED, PGN_REQUEST};
+use crate::commands::{CAN_STATUS, CanStatus, usb_write_dynamic};
+use crate::config::{self, TimeUnsetMode};
+use crate::distance;
+use crate::driver;
+use crate::fault;
+use crate::frame::CanFrame;
+use crate::gear;
+use crate::hal::{CanError, CanReceiver, CanTransmitter};
+use crate::mapping::{PGN_EEC1, PGN_ETC2};
+use crate::power::{self, Broadcast};
+use crate::ramp;
+use crate::request;
+use crate::shaft;
+use crate::speed::Speed;
+use crate::state::{
+    self, DriverState, Faults, TimeState, VehicleState, WorkingState, wait_for_change_where,
+};
+use crate::time::{self, get_current_time_for_j1939};
+use crate::timezone;
+use crate::trip;
+use core::fmt::Write as _;
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use heapless::String;
+use komsi::KomsiDateTime;
+
+use j1939::IdBuilder;
+use j1939::PGN;
+use j1939::spn::{AcknowledgmentMessage, AcknowledgmentType, HighResolutionVehicleDistanceMessage};
+use j1939::spn::{DriverTimeRelatedStates, DriverWorkingState, TachographMessage};
+
+// Channel for 16 frames - buffer for "sending everything once"
+// 16 frames is more than enough for our use case
+pub static CAN_TX_CHANNEL: Channel<CriticalSectionRawMutex, CanFrame, 16> = Channel::new();
+
+// senders that react on state changes wait at least this long between two frames,
+// so a simulator sending very fast does not flood the bus
+const MIN_FRAME_GAP: Duration = Duration::from_millis(10);
+
+/// Sends the queued frames and handles the received ones.
+/// Never returns, the hardware adapters run it as a task.
+pub async fn can_manager<B: CanTransmitter + CanReceiver>(bus: &mut B) {
+    info!("CAN Manager Task started (Combined TX/RX)");
+
+    // claim our address before anything else is sent
+    let claim = address::with_claim(|c| c.start(embassy_time::Instant::now()));
+    let _ = CAN_TX_CHANNEL.try_send(claim);
+    info!("Address claim for {:02X} sent", address::source_address());
+
+    loop {
+        // we save result of select in a variable
+        let selected = select(CAN_TX_CHANNEL.receive(), bus.receive()).await;
+
+        // after  .await is finished the Borrows of 'select' have endet
+        // and wen can use 'bus' again
+
+        match selected {
+            // sending
+            Either::First(frame) => {
+                match embassy_time::with_timeout(Duration::from_millis(100), bus.transmit(&frame))
+                    .await
+                {
+                    Ok(Ok(_)) => {
+                        // success
+                        set_can_status(CanStatus::Ready);
+                    }
+                    Ok(Err(CanError::BusOff)) => {
+                        error!("CAN TX Error: BusOff");
+                        set_can_status(CanStatus::BusOff);
+                        warn!("CAN-Bus-Off detected! Resetting...");
+
+                        bus.restart().await;
+                        info!("Controller restarted.");
+                    }
+                    Ok(Err(CanError::Other(e))) => {
+                        error!("CAN TX Error: {:?}", e.as_str());
+                        set_can_status(CanStatus::TransmitError(e));
+                    }
+                    Err(e) => {
+                        let mut err_msg: String<32> = String::new();
+                        let _ = write!(err_msg, "{:?}", e);
+                        set_can_status(CanStatus::OtherError(err_msg));
+                        warn!("CAN TX Timeout! Controller might be stuck, resetting...");
+
+                        bus.restart().await;
+                        info!("Controller restarted after.");
+                    }
+                }
+            }
+
+            // we should reveive
+            Either::Second(result) => match result {
+                Ok(frame) => {
+                    set_can_status(CanStatus::Ready);
+                    handle_received_frame(&frame).await;
+                }
+                Err(e) => {
+                    let text = match &e {
+                        CanError::BusOff => "BusOff",
+                        CanError::Other(s) => s.as_str(),
+                    };
+                    let mut err_msg: String<32> = String::new();
+                    let _ = err_msg.push_str(text);
+                    set_can_status(CanStatus::ReceiveError(err_msg));
+                    error!("CAN RX hardware error: {:?}", text);
+                    let mut s: String<64> = String::new();
+                    let _ = write!(s, "ERR: CAN RX Error: {}", text);
+                    usb_write_dynamic(s);
+                    Timer::after_millis(100).await;
+                }
+            },
+        }
+    }
+}
+
+fn set_can_status(status: CanStatus) {
+    CAN_STATUS.lock(|s| *s.borrow_mut() = status);
+}
+
+/// Reacts on a frame received from the bus
+pub async fn handle_received_frame(frame: &CanFrame) {
+    let id = frame.id();
+
+    // just for info message
+    info!(
+        "RX ID: ID={:08X} PGN: {:05X} from {:02X}",
+        id,
+        frame.pgn(),
+        frame.source_address()
+    );
+
+    match frame.pgn() {
+        PGN_ADDRESS_CLAIMED => {
+            let mut bytes = [0u8; 8];
+            bytes[..frame.data().len()].copy_from_slice(frame.data());
+            let name = Name::from_u64(u64::from_le_bytes(bytes));
+            let now = embassy_time::Instant::now();
+            let reply =
+                address::with_claim(|c| c.on_address_claimed(frame.source_address(), name, now));
+            if let Some(reply) = reply {
+                warn!(
+                    "Address conflict with {:02X}, our address is now {:02X}",
+                    frame.source_address(),
+                    reply.source_address()
+                );
+                let _ = CAN_TX_CHANNEL.try_send(reply);
+            }
+        }
+        PGN_REQUEST => {
+            // copy, the responders need the state lock
+            let claim = address::with_claim(|c| c.clone());
+            let now = embassy_time::Instant::now();
+            if let Some(reply) = request::handle_request(frame, &claim, now)
+                && CAN_TX_CHANNEL.try_send(reply).is_err()
+            {
+                warn!("Response to request dropped (channel full)");
+            }
+        }
+        // PGN 56832 Reset to us, the MTCO sends it from Source Address 0x17 (ID 0x1CDEEE17)
+        PGN_RESET if frame.destination_address() == address::source_address() => {
+            info!("RESET request ({:08X}) detected, sending response...", id);
+            send_acknowledgment_message().await;
+        }
+        _ => {}
+    }
+}
+
+const PGN_RESET: u32 = 0xDE00;
+
+/// Helper function to put a packet into the queue from anywhere.
+/// Until our address is claimed (or if we lost it) the frame is dropped.
+pub async fn can_send_frame(frame: CanFrame) {
+    if address::may_send() {
+        CAN_TX_CHANNEL.send(frame).await;
+    }
+}
+
+pub fn build_acknowledgment_frame() -> CanFrame {
+    // PGN: Acknowledgment (0xE800 = 59392)
+
+    let id = IdBuilder::from_pgn(PGN::AcknowledgmentMessage)
+        .priority(7)
+        .da(0xFF)
+        .sa(address::source_address())
+        .build();
+
+    let msg = AcknowledgmentMessage {
+        control_byte: Some(AcknowledgmentType::Positive),
+        group_function_value: 0xFF,
+        pgn: PGN::from(PGN_RESET),
+    };
+
+    CanFrame::from_j1939(id, &msg.to_pdu())
+}
+
+pub async fn send_acknowledgment_message() {
+    if !address::may_send() {
+        return;
+    }
+    if CAN_TX_CHANNEL
+        .try_send(build_acknowledgment_frame())
+        .is_err()
+    {
+        warn!("AcknowledgmentMessage dropped (channel full)");
+    } else {
+        info!("AcknowledgmentMessage sent");
+    }
+}
+
+pub fn build_hr_distance_frame(state: &VehicleState) -> CanFrame {
+    // PGN: High Resolution Vehicle Distance (65217 / 0xFEC1)
+    let id = IdBuilder::from_pgn(PGN::HighResolutionVehicleDistance)
+        .priority(6)
+        .sa(address::source_address())
+        .build();
+
+    // Since J1939 often uses u32 for m here (up to 21M km), we adapt u64 accordingly.
+    let msg = HighResolutionVehicleDistanceMessage {
+        total_vehicle_distance_m: Some(state.total_distance as u32), // Total distance in meters
+        trip_distance_m: Some(trip::selected_distance(state) as u32), // Selected trip in meters
+    };
+
+    CanFrame::from_j1939(id, &msg.to_pdu())
+}
+
+pub async fn send_hr_distance_message(state: &VehicleState) {
+    can_send_frame(build_hr_distance_frame(state)).await;
+    info!("HighResolutionVehicleDistanceMessage sent");
+}
+
+/// Sends distance info to Tacho once per second, the distance itself is integrated by
+/// [`distance::distance_loop`]. If the odometer is set by KOMSI the new value is sent at once.
+pub async fn hr_distance_loop() {
+    let mut ticker = Ticker::every(Duration::from_secs(1));
+    loop {
+        if !power::is_enabled(Broadcast::Distance) {
+            power::wait_enabled(Broadcast::Distance).await;
+            ticker.reset();
+        }
+
+        send_hr_distance_message(&state::snapshot()).await;
+        select(ticker.next(), distance::wait_distance_set()).await;
+    }
+}
+
+// the parts of the vehicle state that are in the tachograph frame
+fn tachograph_fields(s: &VehicleState) -> (Speed, bool, bool, DriverState, DriverState, Faults) {
+    (
+        s.speed,
+        s.overspeed,
+        gear::is_forward(s),
+        s.driver1,
+        s.driver2,
+        s.faults,
+    )
+}
+
+/// Sends speed data to Tacho, regularly and as soon as the vehicle state changes
+/// (the distance changes all the time, but it is not in the frame)
+pub async fn tachograph_loop() {
+    let mut changes = state::receiver();
+    let mut next = Instant::now();
+    loop {
+        if !power::is_enabled(Broadcast::Tachograph) {
+            power::wait_enabled(Broadcast::Tachograph).await;
+            next = Instant::now();
+        }
+        let sent = tachograph_fields(&state::snapshot());
+        send_tachograph_message().await;
+
+        // regular 50 milliseconds, but we use some security margin (tacho_interval_ms).
+        // The cycle stays fixed, a lost cycle is not caught up.
+        let interval = Duration::from_millis(config::get().tacho_interval_ms as u64);
+        next = (next + interval).max(Instant::now());
+
+        // a change is sent once before the next cycle, but not closer than MIN_FRAME_GAP
+        // to the frames around it. Later it goes out with the next cycle.
+        Timer::after(MIN_FRAME_GAP).await;
+        let remaining = next
+            .saturating_sub(MIN_FRAME_GAP)
+            .saturating_duration_since(Instant::now());
+        wait_for_change_where(&mut changes, remaining, |s| tachograph_fields(s) != sent).await;
+        if Instant::now() + MIN_FRAME_GAP <= next && power::is_enabled(Broadcast::Tachograph) {
+            send_tachograph_message().await;
+        }
+        Timer::at(next).await;
+    }
+}
+
+fn to_j1939_working_state(state: Option<WorkingState>) -> Option<DriverWorkingState> {
+    state.map(|s| match s {
+        WorkingState::RestSleeping => DriverWorkingState::RestSleeping,
+        WorkingState::DriverAvailable => DriverWorkingState::DriverAvailableShortBreak,
+        WorkingState::Work => DriverWorkingState::Work,
+        WorkingState::Drive => DriverWorkingState::Drive,
+    })
+}
+
+fn to_j1939_time_states(state: Option<TimeState>) -> Option<DriverTimeRelatedStates> {
+    state.map(|s| match s {
+        TimeState::Normal => DriverTimeRelatedStates::NormalNoLimits,
+        TimeState::Before4h30 => DriverTimeRelatedStates::Limit1_15minBefore4_5h,
+        TimeState::Reached4h30 => DriverTimeRelatedStates::Limit2_4_5hReached,
+        TimeState::Before9h => DriverTimeRelatedStates::Limit3_15minBefore9h,
+        TimeState::Reached9h => DriverTimeRelatedStates::Limit4_9hReached,
+        TimeState::Before16h => DriverTimeRelatedStates::Limit5_15minBefore16h,
+        TimeState::Reached16h => DriverTimeRelatedStates::Limit6_16hReached,
+    })
+}
+
+pub fn build_tachograph_frame(state: &VehicleState) -> CanFrame {
+    // PGN Tachograph (65132)
+
+    let id = IdBuilder::from_pgn(PGN::Tachograph)
+        .priority(3)
+        .sa(address::source_address())
+        .build();
+
+    let speed = state.speed;
+
+    let msg = TachographMessage {
+        driver1_working_state: to_j1939_working_state(driver::working_state(
+            &state.driver1,
+            false,
+            speed,
+        )),
+        driver2_working_state: to_j1939_working_state(driver::working_state(
+            &state.driver2,
+            true,
+            speed,
+        )),
+
+        // IMPORTANT: Must be 'true' if the vehicle is moving
+        vehicle_motion: Some(speed.is_moving()),
+
+        driver1_time_states: to_j1939_time_states(state.driver1.time_state),
+        driver1_card_present: Some(state.driver1.card_present),
+
+        // with tolerance, minimum duration and hysteresis, see overspeed.rs
+        vehicle_overspeed: Some(state.overspeed),
+
+        driver2_time_states: to_j1939_time_states(state.driver2.time_state),
+        driver2_card_present: Some(state.driver2.card_present),
+
+        // 'false' usually means "No Event", 'true' could trigger a warning lamp
+        // all three are 'false' unless a fault is injected for training, see fault.rs
+        system_event: Some(fault::is_system_event(&state.faults)),
+
+        // normal operation, no one is in the setup of the "Fahrtenschreiber"
+        handling_information: Some(state.faults.handling),
+
+        // "Fahrtenschreiber" is working without error
+        tachograph_performance: Some(fault::is_performance_degraded(&state.faults)),
+
+        direction_indicator: Some(gear::is_forward(state)), // true = Forward, see gear.rs
+
+        // IMPORTANT: Must not be 0 if the vehicle speed >0
+        // The 1323/1324 compares both values. If the shaft stops but the vehicle moves, the tacho
+        // assumes manipulation (magnet on sensor) and indicates a fault.
+        //
+        // We simulate a plausible value from the calibration of the tacho (k, ratio, tyre)
+        tachograph_output_shaft_speed: Some(shaft::shaft_speed(speed)),
+
+        tachograph_vehicle_speed: Some(speed.kmh() as u16),
+    };
+
+    // the crate takes whole km/h, we put in the full resolution (1/256 km/h)
+    let mut data = msg.to_pdu();
+    data[6..8].copy_from_slice(&speed.to_j1939().to_le_bytes());
+    CanFrame::from_j1939(id, &data)
+}
+
+pub async fn send_tachograph_message() {
+    // one snapshot, so speed and max speed are from the same KOMSI update
+    let state = ramp::tacho_snapshot();
+    can_send_frame(build_tachograph_frame(&state)).await;
+}
+
+// 0.125 rpm per bit, so this is the highest value that fits in 16 bits
+const MAX_ENGINE_RPM: u32 = 8031;
+
+// percent torque with offset -125 %
+const ZERO_TORQUE: u8 = 125;
+
+pub fn build_eec1_frame(state: &VehicleState) -> CanFrame {
+    // PGN Electronic Engine Controller 1 (61444 / 0xF004)
+    // The j1939 crate has no message type for it, so we build the 8 bytes ourselves.
+    // Like all our frames it comes from our claimed address, not from the one of engine #1.
+    let source_address = address::source_address();
+    let id = IdBuilder::from_pgn(PGN::from(PGN_EEC1))
+        .priority(3)
+        .sa(source_address)
+        .build();
+
+    let rpm = state.engine_rpm.min(MAX_ENGINE_RPM) as u16 * 8;
+    let [rpm_lo, rpm_hi] = rpm.to_le_bytes();
+
+    let data = [
+        0xF0,           // torque mode 0 = no request, high resolution torque n/a
+        ZERO_TORQUE,    // driver's demand torque
+        ZERO_TORQUE,    // actual torque
+        rpm_lo,         // engine speed
+        rpm_hi,         //
+        source_address, // controlling device
+        0xFF,           // starter mode n/a
+        ZERO_TORQUE,    // engine demand torque
+    ];
+
+    CanFrame::new(id.as_raw(), &data)
+}
+
+/// Sends the engine speed, so displays that check for a running engine accept the motion
+pub async fn engine_loop() {
+    let mut interval = config::get().engine_interval_ms;
+    let mut ticker = Ticker::every(Duration::from_millis(interval as u64));
+    loop {
+        if !power::is_enabled(Broadcast::Engine) {
+            power::wait_enabled(Broadcast::Engine).await;
+            ticker.reset();
+        }
+        can_send_frame(build_eec1_frame(&state::snapshot())).await;
+        ticker.next().await;
+
+        if config::get().engine_interval_ms != interval {
+            interval = config::get().engine_interval_ms;
+            ticker = Ticker::every(Duration::from_millis(interval as u64));
+        }
+    }
+}
+
+// ETC2 is sent every 100 ms
+const TRANSMISSION_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn build_etc2_frame(state: &VehicleState) -> CanFrame {
+    // PGN Electronic Transmission Controller 2 (61445 / 0xF005)
+    // Like EEC1 the j1939 crate has no message type for it, and it comes from our address.
+    let id = IdBuilder::from_pgn(PGN::from(PGN_ETC2))
+        .priority(6)
+        .sa(address::source_address())
+        .build();
+
+    let gear = gear::gear_code(state);
+    let data = [
+        gear, // selected gear
+        0xFF, // actual gear ratio n/a
+        0xFF, //
+        gear, // current gear
+        0xFF, // requested range n/a
+        0xFF, //
+        0xFF, // current range n/a
+        0xFF, //
+    ];
+
+    CanFrame::new(id.as_raw(), &data)
+}
+
+/// Sends the gear, together with the engine
+pub async fn transmission_loop() {
+    let mut ticker = Ticker::every(TRANSMISSION_INTERVAL);
+    loop {
+        if !power::is_enabled(Broadcast::Engine) {
+            power::wait_enabled(Broadcast::Engine).await;
+            ticker.reset();
+        }
+        can_send_frame(build_etc2_frame(&state::snapshot())).await;
+        ticker.next().await;
+    }
+}
+
+/// Sends datetime info to Tacho
+pub async fn date_time_loop() {
+    loop {
+        power::wait_enabled(Broadcast::TimeDate).await;
+        send_date_time_message().await;
+        Timer::after_millis(config::get().date_time_interval_ms as u64).await;
+    }
+}
+
+/// TimeDate frame of a time in UTC, with the local offset of the configured time zone
+pub fn build_date_time_frame(dt: &KomsiDateTime) -> CanFrame {
+    let (hours, minutes) = timezone::offset_fields(timezone::time_zone().offset_at(dt));
+    let timedate = j1939::spn::TimeDate {
+        year: dt.year as i32,
+        month: dt.month as u32,
+        day: dt.day as u32,
+        hour: dt.hour as u32,
+        minute: dt.min as u32,
+        second: dt.sec as u32,
+        local_hour_offset: Some(hours),
+        local_minute_offset: Some(minutes),
+    };
+    let j1939_id = IdBuilder::from_pgn(PGN::TimeDate)
+        .sa(address::source_address())
+        .build();
+
+    CanFrame::from_j1939(j1939_id, &timedate.to_pdu())
+}
+
+/// TimeDate with all values "not available", while the clock is not set
+pub fn build_date_time_not_available_frame() -> CanFrame {
+    let j1939_id = IdBuilder::from_pgn(PGN::TimeDate)
+        .sa(address::source_address())
+        .build();
+
+    CanFrame::from_j1939(j1939_id, &[0xFF; 8])
+}
+
+/// The TimeDate frame for now, None if there is nothing to send
+pub fn current_date_time_frame() -> Option<CanFrame> {
+    let dt = get_current_time_for_j1939();
+    if time::clock_is_set() {
+        return dt.map(|dt| build_date_time_frame(&dt));
+    }
+    match config::get().time_unset_mode {
+        TimeUnsetMode::Silent => None,
+        TimeUnsetMode::NotAvailable => Some(build_date_time_not_available_frame()),
+        TimeUnsetMode::Send => dt.map(|dt| build_date_time_frame(&dt)),
+    }
+}
+
+pub async fn send_date_time_message() {
+    if let Some(frame) = current_date_time_frame() {
+        can_send_frame(frame).await;
+        info!("Cyclical TimeDate sent");
+    }
+}
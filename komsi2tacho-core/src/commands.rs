@@ -0,0 +1,359 @@
+use crate::config::{self, CommandInput, CommandLine};
+use crate::driver;
+use crate::fault;
+use crate::gear;
+use crate::hal::SerialStream;
+use crate::mapping::{
+    KOMSI_COMMANDS, KomsiMapping, Target, dispatch_by_table, komsi_mapping, report_unsupported,
+};
+use crate::overspeed;
+use crate::reconcile;
+use crate::speed::Speed;
+use crate::state;
+use crate::time::sync_system_time;
+use crate::trip;
+use core::fmt::Write as _;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use heapless::{String, Vec};
+use komsi::KomsiCommand;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LedSignal {
+    None,
+    Ok,
+    Error,
+}
+
+pub static LED_SIGNAL_CHANNEL: Channel<CriticalSectionRawMutex, LedSignal, 4> = Channel::new();
+
+pub fn set_led_signal(signal: LedSignal) {
+    let _ = LED_SIGNAL_CHANNEL.try_send(signal);
+}
+
+#[derive(Debug)]
+pub enum UsbMsg {
+    Static(&'static str),
+    Dynamic(String<64>),
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for UsbMsg {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            UsbMsg::Static(s) => defmt::write!(fmt, "Static({:?})", s),
+            UsbMsg::Dynamic(s) => defmt::write!(fmt, "Dynamic({:?})", s.as_str()),
+        }
+    }
+}
+
+impl UsbMsg {
+    pub fn as_str(&self) -> &str {
+        match self {
+            UsbMsg::Static(s) => s,
+            UsbMsg::Dynamic(s) => s.as_str(),
+        }
+    }
+}
+
+// Channel for USB outgoing messages
+pub static USB_TX_CHANNEL: Channel<CriticalSectionRawMutex, UsbMsg, 8> = Channel::new();
+
+/// Helper to send a message to the USB output channel
+pub fn usb_write(msg: &'static str) {
+    let _ = USB_TX_CHANNEL.try_send(UsbMsg::Static(msg));
+}
+
+/// Helper to send a dynamic message to the USB output channel
+pub fn usb_write_dynamic(msg: String<64>) {
+    let _ = USB_TX_CHANNEL.try_send(UsbMsg::Dynamic(msg));
+}
+
+#[derive(Debug, Clone)]
+pub enum CanStatus {
+    Ready,
+    ReceiveError(String<32>),
+    TransmitError(String<32>),
+    OtherError(String<32>),
+    BusOff,
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for CanStatus {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            CanStatus::Ready => defmt::write!(fmt, "Ready"),
+            CanStatus::ReceiveError(e) => defmt::write!(fmt, "ReceiveError({:?})", e.as_str()),
+            CanStatus::TransmitError(e) => defmt::write!(fmt, "TransmitError({:?})", e.as_str()),
+            CanStatus::OtherError(e) => defmt::write!(fmt, "OtherError({:?})", e.as_str()),
+            CanStatus::BusOff => defmt::write!(fmt, "BusOff"),
+        }
+    }
+}
+
+pub static CAN_STATUS: Mutex<CriticalSectionRawMutex, core::cell::RefCell<CanStatus>> =
+    Mutex::new(core::cell::RefCell::new(CanStatus::Ready));
+
+/// A complete KOMSI command as received: the command letter and its digits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawCommand {
+    pub letter: char,
+    pub digits: Vec<u8, 16>,
+}
+
+/// Splits the KOMSI byte stream into commands.
+///
+/// A command starts with a letter followed by digits (a decimal point is kept for the speed,
+/// see [`Speed::parse`]). It ends with the next letter
+/// or with one of the separators newline, carriage return, ';' or space.
+#[derive(Debug, Default)]
+pub struct KomsiParser {
+    current_cmd: Option<char>,
+    digits: Vec<u8, 16>,
+}
+
+impl KomsiParser {
+    pub const fn new() -> Self {
+        Self {
+            current_cmd: None,
+            digits: Vec::new(),
+        }
+    }
+
+    /// Feeds one byte into the parser, returns a command if one has been completed
+    pub fn push(&mut self, byte: u8) -> Option<RawCommand> {
+        let c = byte as char;
+        if c.is_ascii_alphabetic() {
+            let finished = self.take();
+            self.current_cmd = Some(c);
+            finished
+        } else if c.is_ascii_digit() || c == '.' {
+            if self.current_cmd.is_some() {
+                // too many digits are ignored
+                let _ = self.digits.push(byte);
+            }
+            None
+        } else if c == '\n' || c == '\r' || c == ';' || c == ' ' {
+            self.take()
+        } else {
+            None
+        }
+    }
+
+    fn take(&mut self) -> Option<RawCommand> {
+        let letter = self.current_cmd.take()?;
+        let digits = core::mem::take(&mut self.digits);
+        Some(RawCommand { letter, digits })
+    }
+}
+
+/// Reads KOMSI commands from the serial stream and writes the queued USB messages back.
+/// Never returns, the hardware adapters run it as a task.
+pub async fn komsi_loop<S: SerialStream>(usb: &mut S) {
+    info!("KOMSI Task started");
+
+    // Send welcome message
+    let _ = usb
+        .write_all(
+            concat!(
+                "\r\n--- Komsi2Tacho Version (v",
+                env!("CARGO_PKG_VERSION"),
+                ") ---\r\n--- KOMSI Interface Ready ---\r\n"
+            )
+            .as_bytes(),
+        )
+        .await;
+
+    let mut buffer = [0u8; 64];
+    let mut parser = KomsiParser::new();
+    let mut command_line = CommandLine::new();
+
+    loop {
+        use embassy_futures::select::{Either, select};
+
+        match select(usb.read(&mut buffer), USB_TX_CHANNEL.receive()).await {
+            Either::First(read_result) => match read_result {
+                Ok(len) if len > 0 => {
+                    for &byte in &buffer[..len] {
+                        // no echo for terminal feedback
+                        match command_line.push(byte) {
+                            CommandInput::Komsi(byte) => {
+                                if let Some(cmd) = parser.push(byte) {
+                                    komsi_dispatch(cmd.letter, &cmd.digits);
+                                }
+                            }
+                            CommandInput::Pending => {}
+                            // lines starting with ':' are config commands, the answer can be
+                            // longer than the USB channel, so we write it here
+                            CommandInput::Complete(line) => {
+                                for answer in run_serial_command(&line) {
+                                    let _ = usb.write_all(answer.as_bytes()).await;
+                                    let _ = usb.write_all(b"\r\n").await;
+                                }
+                            }
+                        }
+                    }
+                    let _ = usb.flush().await;
+                }
+                Ok(_) => {}
+                Err(_) => {
+                    error!("USB Read Error");
+                    embassy_time::Timer::after_millis(100).await;
+                }
+            },
+            Either::Second(msg) => {
+                match embassy_time::with_timeout(embassy_time::Duration::from_millis(500), async {
+                    let _ = usb.write_all(msg.as_str().as_bytes()).await;
+                    let _ = usb.write_all(b"\r\n").await;
+                    let _ = usb.flush().await;
+                })
+                .await
+                {
+                    Ok(_) => {}
+                    Err(_) => error!("USB Write Timeout! Host might not be reading."),
+                }
+            }
+        }
+    }
+}
+
+// KOMSI letter of the speed
+const KOMSI_SPEED: char = 'y';
+
+/// Runs a command line from the serial port (without the leading ':')
+pub fn run_serial_command(line: &str) -> config::Output {
+    match line.split_whitespace().next() {
+        Some("odometer") => reconcile::run_command(line),
+        Some("trip") => trip::run_command(line),
+        Some("driver") => driver::run_command(line),
+        Some("fault") => fault::run_command(line),
+        Some("gear") => gear::run_command(line),
+        Some("overspeed") => overspeed::run_command(line),
+        _ => config::run_command(line),
+    }
+}
+
+pub fn komsi_dispatch(cmd_char: char, digits: &[u8]) {
+    // a decimal speed ("y3.5") is our extension, the komsi crate only knows whole km/h
+    if cmd_char == KOMSI_SPEED && digits.contains(&b'.') {
+        match Speed::parse(digits) {
+            Some(speed) => set_speed(speed),
+            None => info!("ERR: KOMSI speed is no number"),
+        }
+        return;
+    }
+
+    // our own letters never go to the komsi crate
+    if let Some(KomsiMapping {
+        target: Target::Extension(_),
+        ..
+    }) = komsi_mapping(cmd_char)
+    {
+        dispatch_by_table(cmd_char, digits);
+        return;
+    }
+
+    match KomsiCommand::from_parts(cmd_char, digits) {
+        Ok(cmd) => {
+            info!("KOMSI command detected: {:?}", cmd);
+
+            // Process the detected command here
+            match cmd {
+                KomsiCommand::DateTime(dt) => {
+                    // Synchronize system time with the received date
+                    sync_system_time(dt);
+                    info!("OK: DateTime synchronized");
+                }
+
+                KomsiCommand::Speed(speed) => set_speed(Speed::from_kmh(speed)),
+
+                KomsiCommand::MaxSpeed(speed) => {
+                    state::update(|s| s.max_speed = speed);
+                    overspeed::check();
+                    info!("OK: MaxSpeed set");
+                }
+
+                // like the speed, without ignition the engine does not run
+                KomsiCommand::RPM(rpm) => {
+                    state::update(|s| s.engine_rpm = if s.ignition { rpm } else { 0 });
+                    info!("OK: RPM set");
+                }
+
+                KomsiCommand::Odometer(dist) => {
+                    let result = reconcile::simulator_odometer(dist);
+                    info!("OK: Odometer {:?}", result);
+                }
+
+                KomsiCommand::InfoRequest(verbose) => {
+                    info!("OK: InfoRequest");
+                    show_info(verbose);
+                }
+
+                // the power task follows the ignition and switches the broadcasts
+                KomsiCommand::Ignition(on) => {
+                    state::update(|s| {
+                        s.ignition = on;
+                        if !on {
+                            s.speed = Speed::ZERO;
+                            s.engine_rpm = 0;
+                        }
+                    });
+                    overspeed::check();
+                    info!("OK: Ignition {}", if on { "ON" } else { "OFF" });
+                }
+
+                // all other commands go by the mapping table
+                _ => dispatch_by_table(cmd_char, digits),
+            }
+        }
+        Err(e) => {
+            info!("ERR: KOMSI command: {:?}", e);
+            if komsi_mapping(cmd_char).is_none() {
+                report_unsupported(cmd_char, None);
+            }
+        }
+    }
+}
+
+fn set_speed(speed: Speed) {
+    // we make sure the tacho never shows more than 125 km/h (speed_clamp) because we do not want to damage the needle
+    let clamp = Speed::from_kmh(config::get().speed_clamp as u32);
+    let safe_speed = speed.min(clamp);
+    let now = embassy_time::Instant::now();
+    state::update(|s| {
+        // without ignition the vehicle does not move
+        s.speed = if s.ignition { safe_speed } else { Speed::ZERO };
+        s.speed_received = Some(now);
+    });
+    overspeed::check();
+    info!("OK: Speed set");
+}
+
+pub fn show_info(verbose: bool) {
+    usb_write("-----");
+    usb_write(concat!("RuhrModding Tacho v", env!("CARGO_PKG_VERSION"),));
+
+    if verbose {
+        usb_write("Model:");
+        usb_write("  esp32c6");
+        usb_write("CAN Info:");
+        usb_write("  GPIO6: TX/CTX");
+        usb_write("  GPIO7: RX/CRX");
+        usb_write("  Speed: 250 kbit/s");
+
+        // which KOMSI commands we really use
+        let mut supported: String<64> = String::new();
+        let _ = supported.push_str("KOMSI: ");
+        for m in KOMSI_COMMANDS.iter().filter(|m| m.is_supported()) {
+            let _ = supported.push(m.letter);
+        }
+        usb_write_dynamic(supported);
+    }
+
+    let status = CAN_STATUS.lock(|s| s.borrow().clone());
+    let mut status_msg: String<64> = String::new();
+    let _ = write!(status_msg, "CAN Status: {:?}", status);
+    usb_write_dynamic(status_msg);
+}
@@ -0,0 +1,806 @@
+//! Runtime configuration.
+//!
+//! The values that used to be constants live in one [`Config`] struct. It can be changed over
+//! the USB serial port with command lines starting with ':' (see [`run_command`]) and is
+//! saved in flash.
+//!
+//! In flash every value is stored with the id of its key, so new keys can be added later
+//! without losing the saved values. Unknown ids are skipped, missing keys keep the default.
+
+use crate::hal::{SECTOR_SIZE, Storage, StorageError};
+use crate::odometer::crc32;
+use crate::shaft::ShaftModel;
+use crate::trip::TRIP_COUNT;
+use core::cell::RefCell;
+use core::fmt::Write as _;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use heapless::{String, Vec};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    /// CAN bit rate in bit/s, used after restart
+    pub can_bitrate: i32,
+    /// preferred J1939 source address, used after restart
+    pub source_address: i32,
+    /// highest speed we send in km/h, protects the needle
+    pub speed_clamp: i32,
+    /// how long we keep sending after ignition off
+    pub run_on_ms: i32,
+    pub tacho_interval_ms: i32,
+    pub engine_interval_ms: i32,
+    pub date_time_interval_ms: i32,
+    pub odometer_save_s: i32,
+    /// calibration of the tacho for the shaft speed, see [`crate::shaft`]
+    pub k_factor: i32,
+    pub axle_ratio_milli: i32,
+    pub tyre_circumference_mm: i32,
+    pub impulses_per_revolution: i32,
+    /// speed ramp for the needle, see [`crate::ramp`]
+    pub speed_ramp_accel: i32,
+    pub speed_ramp_ms: i32,
+    /// how often the distance is integrated
+    pub distance_interval_ms: i32,
+    /// odometer from the simulator, see [`crate::reconcile`]
+    pub odometer_reconcile: bool,
+    pub odometer_slew_m_s: i32,
+    pub odometer_jump_m: i32,
+    /// trip in the HR distance frame, 1..TRIP_COUNT (see [`crate::trip`])
+    pub trip_selected: i32,
+    /// see [`crate::timezone`]
+    pub timezone_offset_min: i32,
+    pub timezone_dst: bool,
+    pub komsi_local_time: bool,
+    /// see [`crate::time`], 0 = measured
+    pub time_scale_milli: i32,
+    pub time_scale_distance: bool,
+    /// see [`crate::time`], YYYYMMDD, 0 = none
+    pub fallback_date: i32,
+    pub time_unset_mode: TimeUnsetMode,
+    /// see [`crate::overspeed`]
+    pub overspeed_tolerance_kmh: i32,
+    pub overspeed_min_s: i32,
+    pub overspeed_hysteresis_kmh: i32,
+    /// injected faults end after this, 0 = only when cleared (see [`crate::fault`])
+    pub fault_timeout_s: i32,
+}
+
+impl Config {
+    pub const fn new() -> Self {
+        Self {
+            can_bitrate: 250_000,
+            source_address: 0xEE,
+            speed_clamp: 125,
+            run_on_ms: 3000,
+            // regular 50 milliseconds, but we use some security margin
+            tacho_interval_ms: 45,
+            engine_interval_ms: 20,
+            date_time_interval_ms: 1000,
+            odometer_save_s: 60,
+            // ratio and tyre unknown, the shaft speed comes from k alone
+            k_factor: 8000,
+            axle_ratio_milli: 0,
+            tyre_circumference_mm: 0,
+            // the shaft speed of the old shaft_factor 1333
+            impulses_per_revolution: 1,
+            // the ramp is off
+            speed_ramp_accel: 0,
+            speed_ramp_ms: 0,
+            distance_interval_ms: 100,
+            odometer_reconcile: true,
+            odometer_slew_m_s: 5,
+            odometer_jump_m: 500,
+            trip_selected: 1,
+            // KOMSI time is UTC
+            timezone_offset_min: 0,
+            timezone_dst: false,
+            komsi_local_time: false,
+            // real time, the distance is not scaled
+            time_scale_milli: 1000,
+            time_scale_distance: false,
+            // no TimeDate until we know the time
+            fallback_date: 0,
+            time_unset_mode: TimeUnsetMode::Silent,
+            // overspeed at once, like before, but without flickering at the limit
+            overspeed_tolerance_kmh: 0,
+            overspeed_min_s: 0,
+            overspeed_hysteresis_kmh: 2,
+            fault_timeout_s: 60,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What the TimeDate frame gets while the clock is not set, see [`crate::time`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TimeUnsetMode {
+    /// no TimeDate at all
+    Silent,
+    /// every field "not available"
+    NotAvailable,
+    /// the time we have
+    Send,
+}
+
+impl TimeUnsetMode {
+    // in the order of the value in flash, and the names for the serial port
+    const ALL: [Self; 3] = [Self::Silent, Self::NotAvailable, Self::Send];
+    const NAMES: &[&str] = &["silent", "not_available", "send"];
+
+    fn from_value(value: i32) -> Self {
+        Self::ALL
+            .get(value as usize)
+            .copied()
+            .unwrap_or(Self::Silent)
+    }
+}
+
+/// One configuration value as seen from the serial port and in flash
+pub struct ConfigKey {
+    /// stored in flash, never change or reuse it
+    pub id: u8,
+    pub name: &'static str,
+    pub min: i32,
+    pub max: i32,
+    /// if not empty only these values are allowed
+    pub allowed: &'static [i32],
+    /// if not empty the value is the index of its name, shown and set by name
+    pub names: &'static [&'static str],
+    pub get: fn(&Config) -> i32,
+    pub set: fn(&mut Config, i32),
+}
+
+impl ConfigKey {
+    pub fn is_valid(&self, value: i32) -> bool {
+        if self.allowed.is_empty() {
+            (self.min..=self.max).contains(&value)
+        } else {
+            self.allowed.contains(&value)
+        }
+    }
+}
+
+pub const CONFIG_KEYS: &[ConfigKey] = &[
+    ConfigKey {
+        id: 1,
+        name: "can_bitrate",
+        min: 125_000,
+        max: 1_000_000,
+        allowed: &[125_000, 250_000, 500_000, 1_000_000],
+        names: &[],
+        get: |c| c.can_bitrate,
+        set: |c, v| c.can_bitrate = v,
+    },
+    ConfigKey {
+        id: 2,
+        name: "source_address",
+        min: 0,
+        max: 253,
+        allowed: &[],
+        names: &[],
+        get: |c| c.source_address,
+        set: |c, v| c.source_address = v,
+    },
+    ConfigKey {
+        id: 3,
+        name: "speed_clamp",
+        min: 0,
+        max: 250,
+        allowed: &[],
+        names: &[],
+        get: |c| c.speed_clamp,
+        set: |c, v| c.speed_clamp = v,
+    },
+    // 4 was shaft_factor, replaced by k_factor, axle_ratio_milli and tyre_circumference_mm
+    ConfigKey {
+        id: 5,
+        name: "run_on_ms",
+        min: 0,
+        max: 600_000,
+        allowed: &[],
+        names: &[],
+        get: |c| c.run_on_ms,
+        set: |c, v| c.run_on_ms = v,
+    },
+    ConfigKey {
+        id: 6,
+        name: "tacho_interval_ms",
+        min: 20,
+        max: 100,
+        allowed: &[],
+        names: &[],
+        get: |c| c.tacho_interval_ms,
+        set: |c, v| c.tacho_interval_ms = v,
+    },
+    ConfigKey {
+        id: 7,
+        name: "engine_interval_ms",
+        min: 10,
+        max: 100,
+        allowed: &[],
+        names: &[],
+        get: |c| c.engine_interval_ms,
+        set: |c, v| c.engine_interval_ms = v,
+    },
+    ConfigKey {
+        id: 8,
+        name: "date_time_interval_ms",
+        min: 100,
+        max: 10_000,
+        allowed: &[],
+        names: &[],
+        get: |c| c.date_time_interval_ms,
+        set: |c, v| c.date_time_interval_ms = v,
+    },
+    ConfigKey {
+        id: 9,
+        name: "odometer_save_s",
+        min: 10,
+        max: 3600,
+        allowed: &[],
+        names: &[],
+        get: |c| c.odometer_save_s,
+        set: |c, v| c.odometer_save_s = v,
+    },
+    ConfigKey {
+        id: 10,
+        name: "k_factor",
+        // the range of w in EU 165/2014 Annex IC
+        min: 4000,
+        max: 25_000,
+        allowed: &[],
+        names: &[],
+        get: |c| c.k_factor,
+        set: |c, v| c.k_factor = v,
+    },
+    ConfigKey {
+        id: 11,
+        name: "axle_ratio_milli",
+        min: 0,
+        max: 30_000,
+        allowed: &[],
+        names: &[],
+        get: |c| c.axle_ratio_milli,
+        set: |c, v| c.axle_ratio_milli = v,
+    },
+    ConfigKey {
+        id: 12,
+        name: "tyre_circumference_mm",
+        min: 0,
+        max: 6000,
+        allowed: &[],
+        names: &[],
+        get: |c| c.tyre_circumference_mm,
+        set: |c, v| c.tyre_circumference_mm = v,
+    },
+    ConfigKey {
+        id: 13,
+        name: "speed_ramp_accel",
+        min: 0,
+        max: 100,
+        allowed: &[],
+        names: &[],
+        get: |c| c.speed_ramp_accel,
+        set: |c, v| c.speed_ramp_accel = v,
+    },
+    ConfigKey {
+        id: 14,
+        name: "speed_ramp_ms",
+        min: 0,
+        max: 2000,
+        allowed: &[],
+        names: &[],
+        get: |c| c.speed_ramp_ms,
+        set: |c, v| c.speed_ramp_ms = v,
+    },
+    ConfigKey {
+        id: 15,
+        name: "distance_interval_ms",
+        min: 10,
+        max: 1000,
+        allowed: &[],
+        names: &[],
+        get: |c| c.distance_interval_ms,
+        set: |c, v| c.distance_interval_ms = v,
+    },
+    ConfigKey {
+        id: 16,
+        name: "odometer_reconcile",
+        min: 0,
+        max: 1,
+        allowed: &[],
+        names: &[],
+        get: |c| c.odometer_reconcile as i32,
+        set: |c, v| c.odometer_reconcile = v != 0,
+    },
+    ConfigKey {
+        id: 17,
+        name: "odometer_slew_m_s",
+        min: 1,
+        max: 100,
+        allowed: &[],
+        names: &[],
+        get: |c| c.odometer_slew_m_s,
+        set: |c, v| c.odometer_slew_m_s = v,
+    },
+    ConfigKey {
+        id: 18,
+        name: "odometer_jump_m",
+        min: 0,
+        max: 100_000,
+        allowed: &[],
+        names: &[],
+        get: |c| c.odometer_jump_m,
+        set: |c, v| c.odometer_jump_m = v,
+    },
+    ConfigKey {
+        id: 19,
+        name: "trip_selected",
+        min: 1,
+        max: TRIP_COUNT as i32,
+        allowed: &[],
+        names: &[],
+        get: |c| c.trip_selected,
+        set: |c, v| c.trip_selected = v,
+    },
+    ConfigKey {
+        id: 20,
+        name: "timezone_offset_min",
+        min: -720,
+        max: 840,
+        allowed: &[],
+        names: &[],
+        get: |c| c.timezone_offset_min,
+        set: |c, v| c.timezone_offset_min = v,
+    },
+    ConfigKey {
+        id: 21,
+        name: "timezone_dst",
+        min: 0,
+        max: 1,
+        allowed: &[],
+        names: &[],
+        get: |c| c.timezone_dst as i32,
+        set: |c, v| c.timezone_dst = v != 0,
+    },
+    ConfigKey {
+        id: 22,
+        name: "komsi_local_time",
+        min: 0,
+        max: 1,
+        allowed: &[],
+        names: &[],
+        get: |c| c.komsi_local_time as i32,
+        set: |c, v| c.komsi_local_time = v != 0,
+    },
+    ConfigKey {
+        id: 23,
+        name: "time_scale_milli",
+        min: 0,
+        max: 100_000,
+        allowed: &[],
+        names: &[],
+        get: |c| c.time_scale_milli,
+        set: |c, v| c.time_scale_milli = v,
+    },
+    ConfigKey {
+        id: 24,
+        name: "time_scale_distance",
+        min: 0,
+        max: 1,
+        allowed: &[],
+        names: &[],
+        get: |c| c.time_scale_distance as i32,
+        set: |c, v| c.time_scale_distance = v != 0,
+    },
+    ConfigKey {
+        id: 25,
+        name: "fallback_date",
+        min: 0,
+        max: 99_991_231,
+        allowed: &[],
+        names: &[],
+        get: |c| c.fallback_date,
+        set: |c, v| c.fallback_date = v,
+    },
+    ConfigKey {
+        id: 26,
+        name: "time_unset_mode",
+        min: 0,
+        max: 2,
+        allowed: &[],
+        names: TimeUnsetMode::NAMES,
+        get: |c| c.time_unset_mode as i32,
+        set: |c, v| c.time_unset_mode = TimeUnsetMode::from_value(v),
+    },
+    ConfigKey {
+        id: 27,
+        name: "overspeed_tolerance_kmh",
+        min: 0,
+        max: 50,
+        allowed: &[],
+        names: &[],
+        get: |c| c.overspeed_tolerance_kmh,
+        set: |c, v| c.overspeed_tolerance_kmh = v,
+    },
+    ConfigKey {
+        id: 28,
+        name: "overspeed_min_s",
+        min: 0,
+        max: 600,
+        allowed: &[],
+        names: &[],
+        get: |c| c.overspeed_min_s,
+        set: |c, v| c.overspeed_min_s = v,
+    },
+    ConfigKey {
+        id: 29,
+        name: "overspeed_hysteresis_kmh",
+        min: 0,
+        max: 50,
+        allowed: &[],
+        names: &[],
+        get: |c| c.overspeed_hysteresis_kmh,
+        set: |c, v| c.overspeed_hysteresis_kmh = v,
+    },
+    ConfigKey {
+        id: 30,
+        name: "fault_timeout_s",
+        min: 0,
+        max: 3600,
+        allowed: &[],
+        names: &[],
+        get: |c| c.fault_timeout_s,
+        set: |c, v| c.fault_timeout_s = v,
+    },
+    ConfigKey {
+        id: 31,
+        name: "impulses_per_revolution",
+        min: 1,
+        max: 64,
+        allowed: &[],
+        names: &[],
+        get: |c| c.impulses_per_revolution,
+        set: |c, v| c.impulses_per_revolution = v,
+    },
+];
+
+pub fn config_key(name: &str) -> Option<&'static ConfigKey> {
+    CONFIG_KEYS.iter().find(|k| k.name == name)
+}
+
+static CONFIG: Mutex<CriticalSectionRawMutex, RefCell<Config>> =
+    Mutex::new(RefCell::new(Config::new()));
+
+// set when the config has to be written to flash
+static CONFIG_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// The current configuration
+pub fn get() -> Config {
+    CONFIG.lock(|c| *c.borrow())
+}
+
+/// Replaces the configuration without saving it (used at boot)
+pub fn set(config: Config) {
+    CONFIG.lock(|c| *c.borrow_mut() = config);
+}
+
+fn change(f: impl FnOnce(&mut Config)) {
+    CONFIG.lock(|c| f(&mut c.borrow_mut()));
+    CONFIG_CHANGED.signal(());
+}
+
+/// Most output lines of one command (list)
+pub const MAX_OUTPUT_LINES: usize = 32;
+
+pub type Output = Vec<String<64>, MAX_OUTPUT_LINES>;
+
+pub(crate) fn line(out: &mut Output, args: core::fmt::Arguments) {
+    let mut s: String<64> = String::new();
+    let _ = s.write_fmt(args);
+    let _ = out.push(s);
+}
+
+// the name for keys with names, else the number
+fn value_text(key: &ConfigKey, value: i32) -> String<24> {
+    let mut s: String<24> = String::new();
+    match key.names.get(value as usize) {
+        Some(name) => {
+            let _ = s.push_str(name);
+        }
+        None => {
+            let _ = write!(s, "{}", value);
+        }
+    }
+    s
+}
+
+// a name of the key or a number
+fn parse_value(key: &ConfigKey, value: &str) -> Option<i32> {
+    match key.names.iter().position(|name| *name == value) {
+        Some(index) => Some(index as i32),
+        None => value.parse().ok(),
+    }
+}
+
+// the keys of the shaft speed, see [`crate::shaft`]
+const SHAFT_KEYS: [&str; 4] = [
+    "k_factor",
+    "axle_ratio_milli",
+    "tyre_circumference_mm",
+    "impulses_per_revolution",
+];
+
+fn show(out: &mut Output, key: &ConfigKey, config: &Config) {
+    let value = value_text(key, (key.get)(config));
+    line(out, format_args!("{} = {}", key.name, value));
+}
+
+/// Runs one configuration command line (without the leading ':') and returns the answer:
+///
+/// ```text
+/// list                all keys with value and range
+/// get <key>
+/// set <key> <value>   checked and saved at once, keys with names also take the name
+/// reset               back to the defaults
+/// ```
+pub fn run_command(command: &str) -> Output {
+    let mut out = Output::new();
+    let mut words = command.split_whitespace();
+
+    match (words.next(), words.next(), words.next(), words.next()) {
+        (Some("list"), None, None, None) => {
+            let config = get();
+            for key in CONFIG_KEYS {
+                let value = value_text(key, (key.get)(&config));
+                if key.names.is_empty() {
+                    line(
+                        &mut out,
+                        format_args!("{} = {} ({}..{})", key.name, value, key.min, key.max),
+                    );
+                } else {
+                    let mut s: String<64> = String::new();
+                    let _ = write!(s, "{} = {} (", key.name, value);
+                    for (i, name) in key.names.iter().enumerate() {
+                        let _ = write!(s, "{}{}", if i == 0 { "" } else { "|" }, name);
+                    }
+                    let _ = s.push(')');
+                    let _ = out.push(s);
+                }
+            }
+        }
+        (Some("get"), Some(name), None, None) => match config_key(name) {
+            Some(key) => show(&mut out, key, &get()),
+            None => line(&mut out, format_args!("ERR: unknown key {}", name)),
+        },
+        (Some("set"), Some(name), Some(value), None) => {
+            match config_key(name).map(|key| (key, parse_value(key, value))) {
+                None => line(&mut out, format_args!("ERR: unknown key {}", name)),
+                Some((key, None)) if !key.names.is_empty() => {
+                    let mut s: String<64> = String::new();
+                    let _ = write!(s, "ERR: {} must be one of", key.name);
+                    for name in key.names {
+                        let _ = write!(s, " {}", name);
+                    }
+                    let _ = out.push(s);
+                }
+                Some((_, None)) => line(&mut out, format_args!("ERR: not a number: {}", value)),
+                Some((key, Some(v))) if !key.is_valid(v) && !key.allowed.is_empty() => {
+                    let mut s: String<64> = String::new();
+                    let _ = write!(s, "ERR: {} must be one of", key.name);
+                    for allowed in key.allowed {
+                        let _ = write!(s, " {}", allowed);
+                    }
+                    let _ = out.push(s);
+                }
+                Some((key, Some(v))) if !key.is_valid(v) => {
+                    line(
+                        &mut out,
+                        format_args!("ERR: {} must be {}..{}", key.name, key.min, key.max),
+                    );
+                }
+                Some((key, Some(v))) => {
+                    change(|c| (key.set)(c, v));
+                    info!("Config {} set to {}", key.name, v);
+                    line(
+                        &mut out,
+                        format_args!("OK: {} = {}", key.name, value_text(key, v)),
+                    );
+                    if SHAFT_KEYS.contains(&key.name)
+                        && !ShaftModel::from_config(&get()).uses_k_factor()
+                    {
+                        line(
+                            &mut out,
+                            format_args!("SHAFT: ratio and tyre are set, k_factor is not used"),
+                        );
+                    }
+                }
+            }
+        }
+        (Some("reset"), None, None, None) => {
+            change(|c| *c = Config::new());
+            line(&mut out, format_args!("OK: config reset"));
+        }
+        _ => line(&mut out, format_args!("ERR: use list, get, set or reset")),
+    }
+    out
+}
+
+/// Collects the command lines starting with ':' from the serial input,
+/// everything else goes to the KOMSI parser.
+#[derive(Debug, Default)]
+pub struct CommandLine {
+    line: Option<String<64>>,
+}
+
+pub enum CommandInput {
+    /// the byte is no part of a command line
+    Komsi(u8),
+    /// the byte was taken
+    Pending,
+    Complete(String<64>),
+}
+
+impl CommandLine {
+    pub const fn new() -> Self {
+        Self { line: None }
+    }
+
+    pub fn push(&mut self, byte: u8) -> CommandInput {
+        match (&mut self.line, byte) {
+            (None, b':') => {
+                self.line = Some(String::new());
+                CommandInput::Pending
+            }
+            (None, _) => CommandInput::Komsi(byte),
+            (Some(_), b'\r' | b'\n') => {
+                CommandInput::Complete(self.line.take().unwrap_or_default())
+            }
+            (Some(line), _) => {
+                // too long lines are cut
+                let _ = line.push(byte as char);
+                CommandInput::Pending
+            }
+        }
+    }
+}
+
+// "KTC" + format version 1
+const MAGIC: u32 = 0x4B54_4301;
+
+// magic, sequence, count, then id + value per key, CRC-32 at the end
+const HEADER_SIZE: usize = 10;
+const ENTRY_SIZE: usize = 5;
+const MAX_ENTRIES: usize = 64;
+const RECORD_BUFFER: usize = align4(HEADER_SIZE + MAX_ENTRIES * ENTRY_SIZE + 4);
+
+// flash drivers like whole words
+const fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Keeps the configuration in two sectors. A new version is written to the sector that
+/// does not hold the current one, so a power loss while saving never loses the old config.
+pub struct ConfigStore<S: Storage> {
+    storage: S,
+    /// sector of the current record, and its sequence number
+    current: Option<(u32, u32)>,
+}
+
+impl<S: Storage> ConfigStore<S> {
+    /// Reads the newest valid config, None if there is none
+    pub fn open(mut storage: S) -> Result<(Self, Option<Config>), StorageError> {
+        if storage.capacity() < 2 * SECTOR_SIZE {
+            return Err(StorageError::OutOfBounds);
+        }
+
+        let mut newest: Option<(u32, u32, Config)> = None;
+        for sector in 0..2 {
+            if let Some((sequence, config)) = read_record(&mut storage, sector * SECTOR_SIZE)?
+                && newest.is_none_or(|(_, n, _)| sequence > n)
+            {
+                newest = Some((sector, sequence, config));
+            }
+        }
+
+        let store = Self {
+            storage,
+            current: newest.map(|(sector, sequence, _)| (sector, sequence)),
+        };
+        Ok((store, newest.map(|(_, _, config)| config)))
+    }
+
+    pub fn save(&mut self, config: &Config) -> Result<(), StorageError> {
+        let (sector, sequence) = match self.current {
+            Some((sector, sequence)) => (1 - sector, sequence.wrapping_add(1)),
+            None => (0, 0),
+        };
+
+        let mut record: Vec<u8, RECORD_BUFFER> = Vec::new();
+        let _ = record.extend_from_slice(&MAGIC.to_le_bytes());
+        let _ = record.extend_from_slice(&sequence.to_le_bytes());
+        let _ = record.extend_from_slice(&(CONFIG_KEYS.len() as u16).to_le_bytes());
+        for key in CONFIG_KEYS {
+            let _ = record.push(key.id);
+            let _ = record.extend_from_slice(&(key.get)(config).to_le_bytes());
+        }
+        let crc = crc32(&record);
+        let _ = record.extend_from_slice(&crc.to_le_bytes());
+        while record.len() < align4(record.len()) {
+            let _ = record.push(0xFF);
+        }
+
+        let offset = sector * SECTOR_SIZE;
+        self.storage.erase_sector(offset)?;
+        self.storage.write(offset, &record)?;
+        self.current = Some((sector, sequence));
+        Ok(())
+    }
+}
+
+fn read_record<S: Storage>(
+    storage: &mut S,
+    offset: u32,
+) -> Result<Option<(u32, Config)>, StorageError> {
+    let mut buffer = [0u8; RECORD_BUFFER];
+    storage.read(offset, &mut buffer[..align4(HEADER_SIZE)])?;
+
+    let word = |b: &[u8], i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+    let count = u16::from_le_bytes([buffer[8], buffer[9]]) as usize;
+    if word(&buffer, 0) != MAGIC || count > MAX_ENTRIES {
+        return Ok(None);
+    }
+
+    let len = HEADER_SIZE + count * ENTRY_SIZE;
+    storage.read(offset, &mut buffer[..align4(len + 4)])?;
+    if word(&buffer, len) != crc32(&buffer[..len]) {
+        return Ok(None);
+    }
+
+    let mut config = Config::new();
+    for entry in buffer[HEADER_SIZE..len].chunks_exact(ENTRY_SIZE) {
+        let value = word(entry, 1) as i32;
+        // unknown or invalid entries keep the default
+        if let Some(key) = CONFIG_KEYS.iter().find(|k| k.id == entry[0])
+            && key.is_valid(value)
+        {
+            (key.set)(&mut config, value);
+        }
+    }
+    Ok(Some((word(&buffer, 4), config)))
+}
+
+/// Opens the store and makes the saved config the current one.
+/// Call it first, before anything reads the config.
+pub fn restore<S: Storage>(storage: S) -> Result<ConfigStore<S>, StorageError> {
+    let (store, saved) = ConfigStore::open(storage)?;
+    match saved {
+        Some(config) => {
+            set(config);
+            info!("Config restored");
+        }
+        None => info!("No saved config found, using defaults"),
+    }
+    Ok(store)
+}
+
+/// Saves the config after every change.
+/// Never returns, the hardware adapters run it as a task.
+pub async fn config_loop<S: Storage>(mut store: ConfigStore<S>) {
+    info!("Config Task started");
+    loop {
+        CONFIG_CHANGED.wait().await;
+        if let Err(e) = store.save(&get()) {
+            error!("Config not saved: {:?}", e);
+            crate::commands::usb_write("ERR: config not saved");
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Distance integration.
+//!
+//! The distance is integrated from the real time between two steps, not from the number
+//! of loops, and nothing is thrown away: the part below one meter is kept in the vehicle
+//! state and counts with the next step. So even 1 km/h moves the odometer, and over a long
+//! drive the tacho stays in sync with the odometer of the simulator.
+//!
+//! The unit of the integration is speed (1/256 km/h) × microseconds, so there is no
+//! rounding at all until a whole meter is reached.
+
+use crate::config;
+use crate::reconcile::{ReconcileSettings, Slew};
+use crate::speed::{SPEED_SCALE, Speed};
+use crate::state::{self, VehicleState};
+use crate::time;
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant};
+
+/// 1/256 km/h × µs per meter: 1 km/h drives 1 m in 3.6 s
+pub const UNITS_PER_METER: u64 = SPEED_SCALE as u64 * 3_600_000;
+
+// set when KOMSI sets the odometer, so the new value is sent at once
+static DISTANCE_SET: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Integrates the speed over time. The speed of a step is held until the next step.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DistanceIntegrator {
+    last: Option<(Instant, Speed)>,
+}
+
+impl DistanceIntegrator {
+    pub const fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// Distance since the last step (in [`UNITS_PER_METER`]), driven with the speed of the
+    /// last step. `speed` is used from now on.
+    pub fn advance(&mut self, speed: Speed, now: Instant) -> u64 {
+        let units = match self.last {
+            Some((then, held)) => {
+                held.raw() as u64 * now.saturating_duration_since(then).as_micros()
+            }
+            None => 0,
+        };
+        self.last = Some((now, speed));
+        units
+    }
+}
+
+/// Adds the distance to the odometer and all trips, whole meters only, the rest is kept.
+/// Returns the meters added.
+pub fn add_distance(s: &mut VehicleState, units: u64) -> u64 {
+    let total = s.distance_remainder.saturating_add(units);
+    let meters = total / UNITS_PER_METER;
+    s.distance_remainder = total % UNITS_PER_METER;
+    s.total_distance = s.total_distance.saturating_add(meters);
+    for trip in &mut s.trips {
+        *trip = trip.saturating_add(meters);
+    }
+    meters
+}
+
+/// Distance driven in real time, with the time scale of the simulation if
+/// `time_scale_distance` is on (see [`crate::time`])
+pub fn scaled(units: u64) -> u64 {
+    if !config::get().time_scale_distance {
+        return units;
+    }
+    (units as u128 * time::time_scale() as u128 / time::REAL_TIME as u128).min(u64::MAX as u128)
+        as u64
+}
+
+/// Tells the distance broadcast that KOMSI has set the odometer
+pub fn distance_set() {
+    DISTANCE_SET.signal(());
+}
+
+/// Waits until KOMSI sets the odometer
+pub async fn wait_distance_set() {
+    DISTANCE_SET.wait().await;
+}
+
+/// Integrates the distance every distance_interval_ms and at once when the speed changes.
+/// Never returns, the hardware adapters run it as a task.
+pub async fn distance_loop() {
+    info!("Distance Task started");
+    let mut changes = state::receiver();
+    let mut integrator = DistanceIntegrator::new();
+    let mut slew = Slew::new();
+    let mut last_step = Instant::now();
+
+    loop {
+        let now = Instant::now();
+        let elapsed_us = now.saturating_duration_since(last_step).as_micros();
+        last_step = now;
+
+        let snapshot = state::snapshot();
+        let speed = snapshot.speed;
+        let units = scaled(integrator.advance(speed, now));
+        if units > 0 || snapshot.odometer_correction != 0 {
+            let rate = ReconcileSettings::from_config(&config::get()).slew_rate;
+            let next = Cell::new(slew);
+            state::update(|s| {
+                let mut slew = next.get();
+                let driven = add_distance(s, units);
+                slew.step(s, driven, elapsed_us, rate);
+                next.set(slew);
+            });
+            slew = next.get();
+        }
+
+        let period = Duration::from_millis(config::get().distance_interval_ms as u64);
+        state::wait_for_change_where(&mut changes, period, |s| s.speed != speed).await;
+    }
+}
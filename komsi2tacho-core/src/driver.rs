@@ -0,0 +1,196 @@
+//! Working state, card and time warnings of the two drivers.
+//!
+//! By default the working state of driver 1 follows the vehicle: Drive while moving, Work
+//! while standing (driver 2 in auto: Available while moving). For training scenarios it can
+//! be set explicitly, together with the cards and the time related states (e.g. the warning
+//! 15 minutes before 4½ h of driving).
+//!
+//! KOMSI has no letters for this, these are our extension (value in brackets):
+//!
+//! ```text
+//! V / Q   working state of driver 1 / 2: 0 rest, 1 available, 2 work, 3 drive,
+//!         7 not available, 9 auto
+//! R       cards: bit 0 driver 1, bit 1 driver 2 (R1 = only driver 1, R3 = both)
+//! S / T   time state of driver 1 / 2: 0 normal, 1 4h15, 2 4h30, 3 8h45, 4 9h, 5 15h45,
+//!         6 16h, 15 not available, 9 auto (driver 1 only, see [`crate::driving_time`])
+//! ```
+//!
+//! Serial commands (after ':'):
+//!
+//! ```text
+//! driver                        both drivers
+//! driver <1|2> <state>          rest, available, work, drive, none or auto
+//! driver <1|2> card <in|out>
+//! driver <1|2> time <warning>   normal, 4h15, 4h30, 8h45, 9h, 15h45, 16h, none or auto
+//! ```
+
+use crate::config::{Output, line};
+use crate::driving_time;
+use crate::speed::Speed;
+use crate::state::{self, DriverState, TimeState, VehicleState, WorkingState};
+
+const WORKING_STATES: &[(&str, WorkingState)] = &[
+    ("rest", WorkingState::RestSleeping),
+    ("available", WorkingState::DriverAvailable),
+    ("work", WorkingState::Work),
+    ("drive", WorkingState::Drive),
+];
+
+const TIME_STATES: &[(&str, TimeState)] = &[
+    ("normal", TimeState::Normal),
+    ("4h15", TimeState::Before4h30),
+    ("4h30", TimeState::Reached4h30),
+    ("8h45", TimeState::Before9h),
+    ("9h", TimeState::Reached9h),
+    ("15h45", TimeState::Before16h),
+    ("16h", TimeState::Reached16h),
+];
+
+/// The working state for the tacho, derived from the speed in auto
+pub fn working_state(driver: &DriverState, second: bool, speed: Speed) -> Option<WorkingState> {
+    if !driver.auto {
+        driver.working_state
+    } else if !speed.is_moving() {
+        Some(WorkingState::Work)
+    } else if second {
+        Some(WorkingState::DriverAvailable)
+    } else {
+        Some(WorkingState::Drive)
+    }
+}
+
+/// KOMSI V/Q, other values are ignored
+pub fn set_working_state(driver: &mut DriverState, value: u32) {
+    match value {
+        0..=3 => {
+            driver.working_state = Some(WORKING_STATES[value as usize].1);
+            driver.auto = false;
+        }
+        7 => {
+            driver.working_state = None;
+            driver.auto = false;
+        }
+        9 => driver.auto = true,
+        _ => {}
+    }
+}
+
+/// KOMSI R
+pub fn set_cards(s: &mut VehicleState, value: u32) {
+    s.driver1.card_present = value & 1 != 0;
+    s.driver2.card_present = value & 2 != 0;
+}
+
+/// KOMSI S/T, other values are ignored
+pub fn set_time_state(driver: &mut DriverState, value: u32) {
+    match value {
+        0..=6 => {
+            driver.time_state = Some(TIME_STATES[value as usize].1);
+            driver.time_auto = false;
+        }
+        15 => {
+            driver.time_state = None;
+            driver.time_auto = false;
+        }
+        9 => driver.time_auto = true,
+        _ => {}
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Change {
+    Working(u32),
+    Card(bool),
+    Time(u32),
+}
+
+fn driver_mut(s: &mut VehicleState, second: bool) -> &mut DriverState {
+    if second {
+        &mut s.driver2
+    } else {
+        &mut s.driver1
+    }
+}
+
+fn name_of<T: PartialEq>(table: &[(&'static str, T)], value: Option<T>) -> &'static str {
+    value
+        .and_then(|v| table.iter().find(|(_, t)| *t == v))
+        .map_or("none", |(name, _)| name)
+}
+
+fn show(out: &mut Output) {
+    let s = state::snapshot();
+    for (i, driver) in [s.driver1, s.driver2].iter().enumerate() {
+        let state = working_state(driver, i == 1, s.speed);
+        line(
+            out,
+            format_args!(
+                "driver {}: {}{}, card {}, time {}{}",
+                i + 1,
+                name_of(WORKING_STATES, state),
+                if driver.auto { " (auto)" } else { "" },
+                if driver.card_present { "in" } else { "out" },
+                name_of(TIME_STATES, driver.time_state),
+                if driver.time_auto { " (auto)" } else { "" },
+            ),
+        );
+    }
+    driving_time::show(out);
+}
+
+/// Runs a driver command line (without the leading ':')
+pub fn run_command(command: &str) -> Output {
+    let mut out = Output::new();
+    let mut words = command.split_whitespace().skip(1);
+
+    let second = match words.next() {
+        None => {
+            show(&mut out);
+            return out;
+        }
+        Some("1") => false,
+        Some("2") => true,
+        Some(_) => {
+            line(&mut out, format_args!("ERR: driver must be 1 or 2"));
+            return out;
+        }
+    };
+
+    // the KOMSI values, so it is the same as V/Q and S/T
+    let change = match (words.next(), words.next(), words.next()) {
+        (Some("card"), Some("in"), None) => Some(Change::Card(true)),
+        (Some("card"), Some("out"), None) => Some(Change::Card(false)),
+        (Some("time"), Some("none"), None) => Some(Change::Time(15)),
+        (Some("time"), Some("auto"), None) => Some(Change::Time(9)),
+        (Some("time"), Some(name), None) => TIME_STATES
+            .iter()
+            .position(|(n, _)| *n == name)
+            .map(|i| Change::Time(i as u32)),
+        (Some("auto"), None, None) => Some(Change::Working(9)),
+        (Some("none"), None, None) => Some(Change::Working(7)),
+        (Some(name), None, None) => WORKING_STATES
+            .iter()
+            .position(|(n, _)| *n == name)
+            .map(|i| Change::Working(i as u32)),
+        _ => None,
+    };
+
+    match change {
+        Some(change) => {
+            state::update(|s| {
+                let driver = driver_mut(s, second);
+                match change {
+                    Change::Working(value) => set_working_state(driver, value),
+                    Change::Card(present) => driver.card_present = present,
+                    Change::Time(value) => set_time_state(driver, value),
+                }
+            });
+            show(&mut out);
+        }
+        None => line(
+            &mut out,
+            format_args!("ERR: use driver <1|2> <state>, card <in|out> or time <warning>"),
+        ),
+    }
+    out
+}
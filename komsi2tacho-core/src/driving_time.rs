@@ -0,0 +1,201 @@
+//! Driving and rest times of driver 1 after EU 561/2006, for the time warnings of the tacho.
+//!
+//! We only know if the vehicle moves, so moving is driving and everything else is a break.
+//! The time comes from the software clock, so a faster simulation also drives the hours
+//! faster. A clock set forward (more than [`MAX_STEP`] at once) counts as rest, also while
+//! driving.
+//!
+//! - continuous driving: at most 4½ h, then a break of 45 min, or 15 min and later 30 min
+//! - daily driving: at most 9 h between two daily rests (at least 9 h)
+//! - the daily rest has to start at the latest 16 h after the last one ended
+//!
+//! 15 minutes before each limit the tacho warns, at the limit it shows it reached. As long
+//! as driver 1 has the time state on auto (`:driver 1 time auto`, KOMSI S9) the state comes
+//! from here.
+
+use crate::config::{Output, line};
+use crate::state::{self, TimeState};
+use crate::time;
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::Timer;
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+
+pub const MAX_CONTINUOUS_DRIVING: u64 = 4 * HOUR + 30 * MINUTE;
+pub const MAX_DAILY_DRIVING: u64 = 9 * HOUR;
+pub const MAX_DUTY_PERIOD: u64 = 16 * HOUR;
+/// the warning comes this long before a limit
+pub const WARNING_BEFORE: u64 = 15 * MINUTE;
+
+pub const BREAK: u64 = 45 * MINUTE;
+/// a split break: first at least 15 min, then at least 30 min
+pub const SPLIT_BREAK_FIRST: u64 = 15 * MINUTE;
+pub const SPLIT_BREAK_SECOND: u64 = 30 * MINUTE;
+pub const DAILY_REST: u64 = 9 * HOUR;
+
+/// A longer step is a clock jump, not driving. The task steps every second, even the
+/// fastest time_scale_milli makes that less than 2 minutes.
+pub const MAX_STEP: u64 = 5 * MINUTE;
+
+/// Driving and rest times in seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DrivingTime {
+    /// driving since the last full break
+    pub continuous: u64,
+    /// driving since the last daily rest
+    pub daily: u64,
+    /// time since the last daily rest ended, 0 until the first drive after it
+    pub duty: u64,
+    /// the current break, 0 while driving
+    pub current_break: u64,
+    /// the first part of a split break is done
+    pub split_break: bool,
+    // the clock (seconds) and if we drove at the last step
+    last: Option<(i64, bool)>,
+}
+
+impl DrivingTime {
+    pub const fn new() -> Self {
+        Self {
+            continuous: 0,
+            daily: 0,
+            duty: 0,
+            current_break: 0,
+            split_break: false,
+            last: None,
+        }
+    }
+
+    /// Counts the time since the last step as what we did then. `now` are the seconds of
+    /// the software clock, a clock set back counts as no time, a jump forward as rest.
+    pub fn step(&mut self, now: i64, driving: bool) {
+        let (elapsed, was_driving) = match self.last {
+            Some((then, was_driving)) => {
+                let elapsed = (now - then).max(0) as u64;
+                (elapsed, was_driving && elapsed <= MAX_STEP)
+            }
+            None => (0, false),
+        };
+        self.last = Some((now, driving));
+
+        if was_driving {
+            self.continuous += elapsed;
+            self.daily += elapsed;
+            self.duty += elapsed;
+        } else {
+            if self.duty > 0 {
+                self.duty += elapsed;
+            }
+            self.current_break += elapsed;
+            if self.current_break >= DAILY_REST {
+                self.daily = 0;
+                self.duty = 0;
+            }
+            if self.is_full_break() {
+                self.continuous = 0;
+            }
+        }
+
+        if driving && !was_driving {
+            // the break ends
+            if self.is_full_break() {
+                self.split_break = false;
+            } else if self.current_break >= SPLIT_BREAK_FIRST {
+                self.split_break = true;
+            }
+            self.current_break = 0;
+        }
+    }
+
+    fn is_full_break(&self) -> bool {
+        self.current_break >= BREAK
+            || (self.split_break && self.current_break >= SPLIT_BREAK_SECOND)
+    }
+
+    /// What the tacho shows: a reached limit before a warning, the longer limit first
+    pub fn time_state(&self) -> TimeState {
+        let limits = [
+            (
+                self.duty,
+                MAX_DUTY_PERIOD,
+                TimeState::Reached16h,
+                TimeState::Before16h,
+            ),
+            (
+                self.daily,
+                MAX_DAILY_DRIVING,
+                TimeState::Reached9h,
+                TimeState::Before9h,
+            ),
+            (
+                self.continuous,
+                MAX_CONTINUOUS_DRIVING,
+                TimeState::Reached4h30,
+                TimeState::Before4h30,
+            ),
+        ];
+        if let Some((.., reached, _)) = limits.iter().find(|(t, max, ..)| t >= max) {
+            return *reached;
+        }
+        if let Some((.., warning)) = limits
+            .iter()
+            .find(|(t, max, ..)| *t + WARNING_BEFORE >= *max)
+        {
+            return *warning;
+        }
+        TimeState::Normal
+    }
+}
+
+static DRIVING_TIME: Mutex<CriticalSectionRawMutex, Cell<DrivingTime>> =
+    Mutex::new(Cell::new(DrivingTime::new()));
+
+/// The current driving and rest times of driver 1
+pub fn driving_time() -> DrivingTime {
+    DRIVING_TIME.lock(|d| d.get())
+}
+
+// 4h30 as "4:30"
+fn hours(out: &mut Output, name: &str, secs: u64) {
+    line(
+        out,
+        format_args!("{} {}:{:02}", name, secs / HOUR, secs % HOUR / MINUTE),
+    );
+}
+
+/// The times for the serial `driver` command
+pub fn show(out: &mut Output) {
+    let d = driving_time();
+    hours(out, "driving continuous", d.continuous);
+    hours(out, "driving daily", d.daily);
+    hours(out, "since daily rest", d.duty);
+    hours(out, "break", d.current_break);
+}
+
+/// Counts the driving times every second and sets the time state of driver 1.
+/// Never returns, the hardware adapters run it as a task.
+pub async fn driving_time_loop() {
+    info!("Driving Time Task started");
+    loop {
+        // without a time there is nothing to count
+        if let Some(now) = time::get_current_time_for_j1939() {
+            let driving = state::snapshot().speed.is_moving();
+            let time_state = DRIVING_TIME.lock(|d| {
+                let mut driving_time = d.get();
+                driving_time.step(time::to_unix(&now), driving);
+                d.set(driving_time);
+                driving_time.time_state()
+            });
+            state::update(|s| {
+                if s.driver1.time_auto {
+                    s.driver1.time_state = Some(time_state);
+                }
+            });
+        }
+        Timer::after_secs(1).await;
+    }
+}
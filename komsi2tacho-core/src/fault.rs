@@ -0,0 +1,227 @@
+//! Tachograph events and faults for training scenarios.
+//!
+//! Normally the tachograph frame says: no event, nobody in the setup, working without error.
+//! For training we can inject these, so trainees see how the tacho reacts:
+//!
+//! - `sensor`: speed sensor fault, a system event with degraded performance
+//! - `power`: power supply interruption, a system event
+//! - `handling`: someone is in the setup of the tacho
+//! - `performance`: the tacho works with degraded performance
+//!
+//! An injected fault ends by itself after `fault_timeout_s` seconds (0 = only when cleared),
+//! so a forgotten fault does not stay for the next trainee.
+//!
+//! KOMSI has no letter for this, this is our extension:
+//!
+//! ```text
+//! Y   bit 0 sensor, bit 1 power, bit 2 handling, bit 3 performance (Y0 clears all)
+//! ```
+//!
+//! Serial commands (after ':'):
+//!
+//! ```text
+//! fault                      all faults
+//! fault <name> [seconds]     injects one, with its own timeout (0 = none)
+//! fault <name> off
+//! fault clear                clears all
+//! ```
+
+use crate::config::{self, Output, line};
+use crate::state::{self, Faults, VehicleState};
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant, Timer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Fault {
+    Sensor,
+    Power,
+    Handling,
+    Performance,
+}
+
+pub const FAULT_COUNT: usize = 4;
+
+/// The names for the serial port, in the order of the KOMSI bits
+pub const FAULTS: [(&str, Fault); FAULT_COUNT] = [
+    ("sensor", Fault::Sensor),
+    ("power", Fault::Power),
+    ("handling", Fault::Handling),
+    ("performance", Fault::Performance),
+];
+
+fn flag(faults: &mut Faults, fault: Fault) -> &mut bool {
+    match fault {
+        Fault::Sensor => &mut faults.sensor,
+        Fault::Power => &mut faults.power,
+        Fault::Handling => &mut faults.handling,
+        Fault::Performance => &mut faults.performance,
+    }
+}
+
+pub fn is_active(faults: &Faults, fault: Fault) -> bool {
+    let mut faults = *faults;
+    *flag(&mut faults, fault)
+}
+
+/// SPN 1621 (system event)
+pub fn is_system_event(faults: &Faults) -> bool {
+    faults.sensor || faults.power
+}
+
+/// SPN 1622 (tachograph performance)
+pub fn is_performance_degraded(faults: &Faults) -> bool {
+    faults.sensor || faults.performance
+}
+
+/// When the injected faults end by themselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FaultTimers {
+    until: [Option<Instant>; FAULT_COUNT],
+}
+
+impl FaultTimers {
+    pub const fn new() -> Self {
+        Self {
+            until: [None; FAULT_COUNT],
+        }
+    }
+
+    /// Starts the timeout of a fault, None = no timeout
+    pub fn start(&mut self, fault: Fault, now: Instant, timeout: Option<Duration>) {
+        self.until[fault as usize] = timeout.map(|t| now + t);
+    }
+
+    pub fn stop(&mut self, fault: Fault) {
+        self.until[fault as usize] = None;
+    }
+
+    /// Time left until the fault ends, None if it has no timeout
+    pub fn remaining(&self, fault: Fault, now: Instant) -> Option<Duration> {
+        self.until[fault as usize].map(|until| until.saturating_duration_since(now))
+    }
+
+    /// The faults whose timeout is over, their timers are stopped
+    pub fn take_expired(&mut self, now: Instant) -> [bool; FAULT_COUNT] {
+        let mut expired = [false; FAULT_COUNT];
+        for (until, expired) in self.until.iter_mut().zip(expired.iter_mut()) {
+            if until.is_some_and(|until| until <= now) {
+                *until = None;
+                *expired = true;
+            }
+        }
+        expired
+    }
+}
+
+static TIMERS: Mutex<CriticalSectionRawMutex, RefCell<FaultTimers>> =
+    Mutex::new(RefCell::new(FaultTimers::new()));
+
+// the timeout from the config, 0 = none
+fn default_timeout() -> Option<u32> {
+    Some(config::get().fault_timeout_s.max(0) as u32).filter(|&s| s > 0)
+}
+
+/// Switches a fault on (with the timeout in seconds, None = none) or off
+pub fn set(s: &mut VehicleState, fault: Fault, on: bool, timeout_s: Option<u32>) {
+    *flag(&mut s.faults, fault) = on;
+    TIMERS.lock(|t| {
+        let mut t = t.borrow_mut();
+        match timeout_s {
+            Some(secs) if on => t.start(
+                fault,
+                Instant::now(),
+                Some(Duration::from_secs(secs as u64)),
+            ),
+            _ => t.stop(fault),
+        }
+    });
+}
+
+/// KOMSI Y, each bit switches one fault with the timeout from the config
+pub fn set_faults(s: &mut VehicleState, value: u32) {
+    let timeout = default_timeout();
+    for (i, (_, fault)) in FAULTS.iter().enumerate() {
+        let on = value & 1 << i != 0;
+        // a fault that is already on keeps its timeout
+        if on != is_active(&s.faults, *fault) {
+            set(s, *fault, on, timeout);
+        }
+    }
+}
+
+fn show(out: &mut Output) {
+    let faults = state::snapshot().faults;
+    let timers = TIMERS.lock(|t| *t.borrow());
+    let now = Instant::now();
+    for (name, fault) in FAULTS {
+        match (is_active(&faults, fault), timers.remaining(fault, now)) {
+            (false, _) => line(out, format_args!("fault {}: off", name)),
+            (true, None) => line(out, format_args!("fault {}: on", name)),
+            (true, Some(left)) => line(
+                out,
+                format_args!("fault {}: on, {} s left", name, left.as_secs()),
+            ),
+        }
+    }
+}
+
+/// Runs a fault command line (without the leading ':')
+pub fn run_command(command: &str) -> Output {
+    let mut out = Output::new();
+    let mut words = command.split_whitespace().skip(1);
+
+    let first = words.next();
+    let fault = first.and_then(|w| FAULTS.iter().find(|(n, _)| *n == w).map(|(_, f)| *f));
+    match (first, fault, words.next(), words.next()) {
+        (None, ..) => {}
+        (Some("clear"), _, None, _) => state::update(|s| {
+            for (_, fault) in FAULTS {
+                set(s, fault, false, None);
+            }
+        }),
+        (_, Some(fault), None, _) => {
+            let timeout = default_timeout();
+            state::update(|s| set(s, fault, true, timeout));
+        }
+        (_, Some(fault), Some("off"), None) => state::update(|s| set(s, fault, false, None)),
+        (_, Some(fault), Some(secs), None) => match secs.parse::<u32>() {
+            Ok(secs) => state::update(|s| set(s, fault, true, Some(secs).filter(|&s| s > 0))),
+            Err(_) => {
+                line(&mut out, format_args!("ERR: not a number: {}", secs));
+                return out;
+            }
+        },
+        _ => {
+            line(
+                &mut out,
+                format_args!("ERR: use fault <sensor|power|handling|performance> [seconds|off]"),
+            );
+            return out;
+        }
+    }
+    show(&mut out);
+    out
+}
+
+/// Ends the injected faults when their timeout is over.
+/// Never returns, the hardware adapters run it as a task.
+pub async fn fault_loop() {
+    info!("Fault Task started");
+    loop {
+        let expired = TIMERS.lock(|t| t.borrow_mut().take_expired(Instant::now()));
+        if expired.contains(&true) {
+            info!("Faults over: {:?}", expired);
+            state::update(|s| {
+                for (i, (_, fault)) in FAULTS.iter().enumerate() {
+                    if expired[i] {
+                        *flag(&mut s.faults, *fault) = false;
+                    }
+                }
+            });
+        }
+        Timer::after_millis(100).await;
+    }
+}
@@ -0,0 +1,68 @@
+use j1939::{FrameBuilder, Id};
+
+/// A CAN frame with 29-bit extended identifier, as we use it for J1939.
+///
+/// This is our own small frame type so the core does not depend on a CAN driver.
+/// The hardware adapters convert it to and from their native frame types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CanFrame {
+    id: u32,
+    data: [u8; 8],
+    len: u8,
+}
+
+impl CanFrame {
+    /// Creates a frame, the id is masked to 29 bits and data is cut after 8 bytes
+    pub fn new(id: u32, data: &[u8]) -> Self {
+        let len = data.len().min(8);
+        let mut buffer = [0u8; 8];
+        buffer[..len].copy_from_slice(&data[..len]);
+        Self {
+            id: id & 0x1FFF_FFFF,
+            data: buffer,
+            len: len as u8,
+        }
+    }
+
+    /// Builds a frame from a J1939 id and the PDU of a message
+    pub fn from_j1939(id: Id, pdu: &[u8]) -> Self {
+        let frame = FrameBuilder::new(id).copy_from_slice(pdu).build();
+        let data = frame.pdu();
+        Self::new(id.as_raw(), data)
+    }
+
+    /// Raw 29-bit identifier
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.len as usize]
+    }
+
+    /// J1939 parameter group number (Bits 8-25 of the 29-bit ID).
+    /// For PDU1 messages (PF < 240) the destination address is removed.
+    pub fn pgn(&self) -> u32 {
+        let pgn = (self.id >> 8) & 0x3FFFF;
+        if (pgn >> 8) & 0xFF < 0xF0 {
+            pgn & 0x3FF00
+        } else {
+            pgn
+        }
+    }
+
+    /// J1939 destination address, global (0xFF) for PDU2 messages
+    pub fn destination_address(&self) -> u8 {
+        if (self.id >> 16) & 0xFF < 0xF0 {
+            ((self.id >> 8) & 0xFF) as u8
+        } else {
+            0xFF
+        }
+    }
+
+    /// J1939 source address
+    pub fn source_address(&self) -> u8 {
+        (self.id & 0xFF) as u8
+    }
+}
@@ -0,0 +1,123 @@
+//! Direction of travel and gear.
+//!
+//! The tacho gets the direction in the tachograph frame (forward also in neutral, a standing
+//! vehicle does not go backwards), the gear goes out as current gear in ETC2 for clusters
+//! and other displays. Without input we drive forward in gear 1, like before.
+//!
+//! KOMSI has no letters for this, these are our extension:
+//!
+//! ```text
+//! W   direction: 0 neutral, 1 forward, 2 reverse
+//! Z   gear number, 1..MAX_GEAR
+//! ```
+//!
+//! Serial commands (after ':'):
+//!
+//! ```text
+//! gear                             direction and gear
+//! gear <forward|neutral|reverse>
+//! gear <n>                         gear number
+//! ```
+
+use crate::config::{Output, line};
+use crate::state::{self, Direction, VehicleState};
+
+/// Highest gear number, SPN 523 goes up to 125
+pub const MAX_GEAR: u8 = 125;
+
+// SPN 523 (current gear) with offset -125: negative is reverse, 0 neutral
+const GEAR_OFFSET: i32 = 125;
+
+const DIRECTIONS: &[(&str, Direction)] = &[
+    ("neutral", Direction::Neutral),
+    ("forward", Direction::Forward),
+    ("reverse", Direction::Reverse),
+];
+
+/// KOMSI W, other values are ignored
+pub fn set_direction(s: &mut VehicleState, value: u32) {
+    if let Some((_, direction)) = DIRECTIONS.get(value as usize) {
+        s.direction = *direction;
+    }
+}
+
+/// KOMSI Z, other values are ignored
+pub fn set_gear(s: &mut VehicleState, value: u32) {
+    if (1..=MAX_GEAR as u32).contains(&value) {
+        s.gear = value as u8;
+    }
+}
+
+/// Forward for the direction indicator of the tachograph frame
+pub fn is_forward(s: &VehicleState) -> bool {
+    s.direction != Direction::Reverse
+}
+
+/// The value of SPN 523 (current gear) and 524 (selected gear) for ETC2
+pub fn gear_code(s: &VehicleState) -> u8 {
+    let gear = s.gear.clamp(1, MAX_GEAR) as i32;
+    let code = match s.direction {
+        Direction::Forward => GEAR_OFFSET + gear,
+        Direction::Neutral => GEAR_OFFSET,
+        Direction::Reverse => GEAR_OFFSET - gear,
+    };
+    code as u8
+}
+
+fn show(out: &mut Output) {
+    let s = state::snapshot();
+    let name = DIRECTIONS
+        .iter()
+        .find(|(_, d)| *d == s.direction)
+        .map_or("?", |(name, _)| name);
+    match s.direction {
+        Direction::Neutral => line(out, format_args!("gear: {}", name)),
+        _ => line(out, format_args!("gear: {} {}", name, s.gear)),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Change {
+    Direction(u32),
+    Gear(u32),
+}
+
+/// Runs a gear command line (without the leading ':')
+pub fn run_command(command: &str) -> Output {
+    let mut out = Output::new();
+    let mut words = command.split_whitespace().skip(1);
+
+    // the KOMSI values, so it is the same as W and Z
+    let change = match (words.next(), words.next()) {
+        (None, _) => {
+            show(&mut out);
+            return out;
+        }
+        (Some(word), None) => match DIRECTIONS.iter().position(|(n, _)| *n == word) {
+            Some(i) => Some(Change::Direction(i as u32)),
+            None => match word.parse::<u32>() {
+                Ok(n) if (1..=MAX_GEAR as u32).contains(&n) => Some(Change::Gear(n)),
+                _ => None,
+            },
+        },
+        _ => None,
+    };
+
+    match change {
+        Some(change) => {
+            state::update(|s| match change {
+                Change::Direction(value) => set_direction(s, value),
+                Change::Gear(value) => set_gear(s, value),
+            });
+            show(&mut out);
+        }
+        None => line(
+            &mut out,
+            format_args!(
+                "ERR: use gear <forward|neutral|reverse> or gear <1..{}>",
+                MAX_GEAR
+            ),
+        ),
+    }
+    out
+}
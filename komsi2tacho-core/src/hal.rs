@@ -0,0 +1,83 @@
+//! Traits the hardware (or the simulator) has to implement to run the core.
+
+use embedded_io_async::{Read, Write};
+use heapless::String;
+
+/// Error returned by a CAN driver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CanError {
+    /// the controller went bus-off and has to be restarted
+    BusOff,
+    /// any other driver error, as text for the status output
+    Other(String<32>),
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for CanError {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            CanError::BusOff => defmt::write!(fmt, "BusOff"),
+            CanError::Other(e) => defmt::write!(fmt, "Other({:?})", e.as_str()),
+        }
+    }
+}
+
+/// Sending side of a CAN controller
+#[allow(async_fn_in_trait)]
+pub trait CanTransmitter {
+    async fn transmit(&mut self, frame: &crate::frame::CanFrame) -> Result<(), CanError>;
+
+    /// Stops the controller, waits a moment and starts it again.
+    /// Used to recover from bus-off and stuck transmissions.
+    async fn restart(&mut self);
+}
+
+/// Receiving side of a CAN controller
+#[allow(async_fn_in_trait)]
+pub trait CanReceiver {
+    async fn receive(&mut self) -> Result<crate::frame::CanFrame, CanError>;
+}
+
+/// Byte stream the KOMSI commands are read from and the answers are written to
+/// (USB-Serial-JTAG on the ESP32, a pty in the simulator)
+pub trait SerialStream: Read + Write {}
+
+impl<T: Read + Write> SerialStream for T {}
+
+/// Error returned by the storage of the persistent values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageError {
+    /// offset or length outside of the storage
+    OutOfBounds,
+    /// any other driver error, as text for the status output
+    Other(String<32>),
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for StorageError {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            StorageError::OutOfBounds => defmt::write!(fmt, "OutOfBounds"),
+            StorageError::Other(e) => defmt::write!(fmt, "Other({:?})", e.as_str()),
+        }
+    }
+}
+
+/// Erase unit of the storage, 4 KiB like the ESP32 flash
+pub const SECTOR_SIZE: u32 = 4096;
+
+/// Flash region for values that survive a power cycle (odometer).
+///
+/// It behaves like NOR flash: erased bytes are 0xFF and a write can only clear bits,
+/// so every byte has to be erased before it is written again.
+pub trait Storage {
+    /// size of the region in bytes, a multiple of [`SECTOR_SIZE`]
+    fn capacity(&self) -> u32;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), StorageError>;
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), StorageError>;
+
+    /// Erases the sector starting at `offset`
+    fn erase_sector(&mut self, offset: u32) -> Result<(), StorageError>;
+}
@@ -0,0 +1,35 @@
+//! Hardware independent core of Komsi2Tacho.
+//!
+//! Everything between the KOMSI byte stream and the J1939 frames lives here, so it can be
+//! tested on the PC (`cargo test -p komsi2tacho-core --target <host-target>`).
+//! The ESP32-C6 firmware only adds the drivers (see [`hal`]) and spawns the tasks.
+#![no_std]
+
+// must be first, the other modules use the logging macros
+#[macro_use]
+mod fmt;
+
+pub mod address;
+pub mod can;
+pub mod commands;
+pub mod config;
+pub mod distance;
+pub mod driver;
+pub mod driving_time;
+pub mod fault;
+pub mod frame;
+pub mod gear;
+pub mod hal;
+pub mod mapping;
+pub mod odometer;
+pub mod overspeed;
+pub mod power;
+pub mod ramp;
+pub mod reconcile;
+pub mod request;
+pub mod shaft;
+pub mod speed;
+pub mod state;
+pub mod time;
+pub mod timezone;
+pub mod trip;
@@ -0,0 +1,217 @@
+//! Which KOMSI command ends up where.
+//!
+//! One row per command of the KOMSI protocol: the vehicle state it updates and the J1939 PGN
+//! that carries it to the tacho. Commands the tacho cannot show are answered with an
+//! "unsupported" message over USB, so the simulator plugins know what we really use.
+
+use crate::commands::usb_write_dynamic;
+use crate::driver;
+use crate::fault;
+use crate::gear;
+use crate::state::{self, VehicleState};
+use crate::trip;
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicU64, Ordering};
+use heapless::String;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Target {
+    /// handled in komsi_dispatch with the command parsed by the komsi crate
+    Command,
+    /// the value is written into the vehicle state
+    State(fn(&mut VehicleState, u32)),
+    /// like State, but our own letter the komsi crate does not know
+    Extension(fn(&mut VehicleState, u32)),
+    /// known KOMSI command, but there is nothing on the tacho to show it
+    Unsupported,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct KomsiMapping {
+    pub letter: char,
+    pub name: &'static str,
+    pub target: Target,
+    /// PGN that carries the value, None if it is not sent on the bus
+    pub pgn: Option<u32>,
+}
+
+impl KomsiMapping {
+    pub fn is_supported(&self) -> bool {
+        !matches!(self.target, Target::Unsupported)
+    }
+}
+
+const fn row(letter: char, name: &'static str, target: Target, pgn: Option<u32>) -> KomsiMapping {
+    KomsiMapping {
+        letter,
+        name,
+        target,
+        pgn,
+    }
+}
+
+// PGNs we send
+pub const PGN_TACHOGRAPH: u32 = 65132;
+pub const PGN_HR_DISTANCE: u32 = 65217;
+pub const PGN_TIME_DATE: u32 = 65254;
+pub const PGN_EEC1: u32 = 61444;
+pub const PGN_ETC2: u32 = 61445;
+
+pub const KOMSI_COMMANDS: &[KomsiMapping] = &[
+    // the letters of the komsi crate, in its order.
+    // The ignition switches the broadcasts on and off, see power.rs
+    row('A', "ignition", Target::Command, None),
+    row('B', "engine", Target::Unsupported, None),
+    row('C', "passenger doors open", Target::Unsupported, None),
+    row('D', "indicator", Target::Unsupported, None),
+    row('E', "fixing brake", Target::Unsupported, None),
+    // the lamps are not on any PGN we send
+    row('F', "warning lights", Target::Unsupported, None),
+    row('G', "main lights", Target::Unsupported, None),
+    row('H', "front door", Target::Unsupported, None),
+    row('I', "second door", Target::Unsupported, None),
+    row('J', "third door", Target::Unsupported, None),
+    row('K', "stop request", Target::Unsupported, None),
+    row('L', "stop brake", Target::Unsupported, None),
+    row('M', "high beam", Target::Unsupported, None),
+    row('N', "battery light", Target::Unsupported, None),
+    row('O', "simulator type", Target::Unsupported, None),
+    row('P', "door clearance", Target::Unsupported, None),
+    row('d', "debug mode", Target::Unsupported, None),
+    row('i', "info request", Target::Command, None),
+    row('o', "odometer", Target::Command, Some(PGN_HR_DISTANCE)),
+    row('p', "protocol switch", Target::Unsupported, None),
+    row('r', "date time", Target::Command, Some(PGN_TIME_DATE)),
+    // the tacho shows the overspeed flag
+    row('s', "max speed", Target::Command, Some(PGN_TACHOGRAPH)),
+    row('t', "rpm", Target::Command, Some(PGN_EEC1)),
+    row('u', "pressure", Target::Unsupported, None),
+    row('v', "temperature", Target::Unsupported, None),
+    row('w', "oil", Target::Unsupported, None),
+    row('x', "fuel", Target::Unsupported, None),
+    row('y', "speed", Target::Command, Some(PGN_TACHOGRAPH)),
+    row('z', "water", Target::Unsupported, None),
+    // our extension with letters the komsi crate does not use, see driver.rs
+    row(
+        'V',
+        "driver 1 working state",
+        Target::Extension(|s, v| driver::set_working_state(&mut s.driver1, v)),
+        Some(PGN_TACHOGRAPH),
+    ),
+    row(
+        'Q',
+        "driver 2 working state",
+        Target::Extension(|s, v| driver::set_working_state(&mut s.driver2, v)),
+        Some(PGN_TACHOGRAPH),
+    ),
+    row(
+        'R',
+        "driver cards",
+        Target::Extension(driver::set_cards),
+        Some(PGN_TACHOGRAPH),
+    ),
+    row(
+        'S',
+        "driver 1 time state",
+        Target::Extension(|s, v| driver::set_time_state(&mut s.driver1, v)),
+        Some(PGN_TACHOGRAPH),
+    ),
+    row(
+        'T',
+        "driver 2 time state",
+        Target::Extension(|s, v| driver::set_time_state(&mut s.driver2, v)),
+        Some(PGN_TACHOGRAPH),
+    ),
+    // see fault.rs
+    row(
+        'Y',
+        "tachograph faults",
+        Target::Extension(fault::set_faults),
+        Some(PGN_TACHOGRAPH),
+    ),
+    // see gear.rs
+    row(
+        'W',
+        "direction",
+        Target::Extension(gear::set_direction),
+        Some(PGN_TACHOGRAPH),
+    ),
+    row(
+        'Z',
+        "gear",
+        Target::Extension(gear::set_gear),
+        Some(PGN_ETC2),
+    ),
+    // see trip.rs
+    row(
+        'U',
+        "trip reset",
+        Target::Extension(trip::reset_trips),
+        Some(PGN_HR_DISTANCE),
+    ),
+];
+
+/// Row for the given KOMSI letter, None if we do not know the command at all
+pub fn komsi_mapping(letter: char) -> Option<&'static KomsiMapping> {
+    KOMSI_COMMANDS.iter().find(|m| m.letter == letter)
+}
+
+// Letters already answered with "unsupported", so a plugin sending e.g. the fuel all the time
+// does not flood the USB output. Bit 0-25 = 'A'-'Z', bit 26-51 = 'a'-'z'.
+static REPORTED: AtomicU64 = AtomicU64::new(0);
+
+fn letter_bit(letter: char) -> u64 {
+    match letter {
+        'A'..='Z' => 1 << (letter as u8 - b'A'),
+        'a'..='z' => 1 << (26 + letter as u8 - b'a'),
+        _ => 0,
+    }
+}
+
+/// Handles a command that komsi_dispatch has no own logic for
+pub fn dispatch_by_table(letter: char, digits: &[u8]) {
+    match komsi_mapping(letter) {
+        Some(KomsiMapping {
+            target: Target::State(apply) | Target::Extension(apply),
+            name,
+            ..
+        }) => match parse_value(digits) {
+            Some(value) => {
+                state::update(|s| apply(s, value));
+                info!("OK: {} set", name);
+            }
+            None => warn!("ERR: KOMSI {} ({}) is no number", letter, name),
+        },
+        Some(m) if m.is_supported() => {
+            // parsed by the komsi crate, but with a value it did not accept
+            warn!("KOMSI command {} without handler", letter);
+        }
+        Some(m) => report_unsupported(letter, Some(m.name)),
+        None => report_unsupported(letter, None),
+    }
+}
+
+/// Tells the USB side once per letter that we ignore this command
+pub fn report_unsupported(letter: char, name: Option<&str>) {
+    info!("KOMSI command {} not supported", letter);
+
+    let bit = letter_bit(letter);
+    if REPORTED.fetch_or(bit, Ordering::Relaxed) & bit != 0 {
+        return;
+    }
+    let mut s: String<64> = String::new();
+    let _ = match name {
+        Some(name) => write!(s, "ERR: KOMSI {} ({}) not supported", letter, name),
+        None => write!(s, "ERR: KOMSI {} unknown", letter),
+    };
+    usb_write_dynamic(s);
+}
+
+/// The value of a command the table handles, None if there is anything but digits
+/// (the parser also passes the '.' of a decimal speed)
+pub fn parse_value(digits: &[u8]) -> Option<u32> {
+    digits.iter().try_fold(0u32, |v, d| {
+        let digit = d.checked_sub(b'0').filter(|d| *d < 10)?;
+        Some(v.saturating_mul(10).saturating_add(digit as u32))
+    })
+}
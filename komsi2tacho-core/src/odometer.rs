@@ -0,0 +1,221 @@
+//! Odometer that survives a power cycle.
+//!
+//! The distances are written as small records one after the other through the whole storage,
+//! so every sector is erased equally often (wear levelling). A record is only valid with
+//! magic and CRC, so a write cut by a power loss is just ignored and we restore the record
+//! before it. On boot the valid record with the highest sequence number wins.
+
+use crate::config;
+use crate::hal::{SECTOR_SIZE, Storage, StorageError};
+use crate::state::{self, wait_for_change};
+use crate::time;
+use crate::trip::TRIP_COUNT;
+use embassy_time::{Duration, Instant};
+
+pub const RECORD_SIZE: u32 = 64;
+
+// "KTO" + format version 1
+const MAGIC: u32 = 0x4B54_4F01;
+
+// how often a changed distance is written while the ignition is on
+fn save_interval() -> Duration {
+    Duration::from_secs(config::get().odometer_save_s as u64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OdometerRecord {
+    pub sequence: u32,
+    /// in meters
+    pub total_distance: u64,
+    /// in meters
+    pub trips: [u64; TRIP_COUNT],
+    /// UTC in seconds since 1970 when it was saved, None if the clock was not set
+    pub time: Option<i64>,
+}
+
+impl OdometerRecord {
+    /// magic, sequence, total, trips, time (0xFF if None), 0xFF padding, CRC-32 of the bytes
+    /// before it. The padding from byte 48 on is kept free for more fields of the same format.
+    pub fn to_bytes(&self) -> [u8; RECORD_SIZE as usize] {
+        let mut bytes = [0xFF; RECORD_SIZE as usize];
+        bytes[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.total_distance.to_le_bytes());
+        for (i, trip) in self.trips.iter().enumerate() {
+            bytes[16 + 8 * i..24 + 8 * i].copy_from_slice(&trip.to_le_bytes());
+        }
+        if let Some(time) = self.time {
+            bytes[40..48].copy_from_slice(&time.to_le_bytes());
+        }
+        let end = RECORD_SIZE as usize - 4;
+        let crc = crc32(&bytes[..end]);
+        bytes[end..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// None for erased, torn or foreign data
+    pub fn from_bytes(bytes: &[u8; RECORD_SIZE as usize]) -> Option<Self> {
+        let word =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let dword = |i: usize| u64::from(word(i)) | u64::from(word(i + 4)) << 32;
+
+        let end = RECORD_SIZE as usize - 4;
+        if word(0) != MAGIC || word(end) != crc32(&bytes[..end]) {
+            return None;
+        }
+        Some(Self {
+            sequence: word(4),
+            total_distance: dword(8),
+            trips: core::array::from_fn(|i| dword(16 + 8 * i)),
+            time: Some(dword(40)).filter(|t| *t != u64::MAX).map(|t| t as i64),
+        })
+    }
+}
+
+/// CRC-32 (IEEE), bitwise because we only check a few bytes
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Writes the odometer records into a [`Storage`]
+pub struct OdometerStore<S: Storage> {
+    storage: S,
+    next_slot: u32,
+    last: Option<OdometerRecord>,
+}
+
+impl<S: Storage> OdometerStore<S> {
+    /// Searches the newest record. The storage needs at least two sectors, so the newest
+    /// record is never in the sector we erase.
+    pub fn open(mut storage: S) -> Result<Self, StorageError> {
+        if storage.capacity() < 2 * SECTOR_SIZE || !storage.capacity().is_multiple_of(SECTOR_SIZE) {
+            return Err(StorageError::OutOfBounds);
+        }
+
+        let slots = storage.capacity() / RECORD_SIZE;
+        let mut newest: Option<(u32, OdometerRecord)> = None;
+        let mut bytes = [0u8; RECORD_SIZE as usize];
+        for slot in 0..slots {
+            storage.read(slot * RECORD_SIZE, &mut bytes)?;
+            if let Some(record) = OdometerRecord::from_bytes(&bytes)
+                && newest.is_none_or(|(_, n)| record.sequence > n.sequence)
+            {
+                newest = Some((slot, record));
+            }
+        }
+
+        Ok(Self {
+            next_slot: newest.map_or(0, |(slot, _)| (slot + 1) % slots),
+            last: newest.map(|(_, record)| record),
+            storage,
+        })
+    }
+
+    /// The newest record, None on an empty storage
+    pub fn last(&self) -> Option<OdometerRecord> {
+        self.last
+    }
+
+    pub fn save(
+        &mut self,
+        total_distance: u64,
+        trips: [u64; TRIP_COUNT],
+        time: Option<i64>,
+    ) -> Result<(), StorageError> {
+        let record = OdometerRecord {
+            sequence: self.last.map_or(0, |r| r.sequence.wrapping_add(1)),
+            total_distance,
+            trips,
+            time,
+        };
+
+        let slots = self.storage.capacity() / RECORD_SIZE;
+        let slots_per_sector = SECTOR_SIZE / RECORD_SIZE;
+        let mut bytes = [0u8; RECORD_SIZE as usize];
+
+        // a slot cut by a power loss is not erased, then we take the next one
+        for _ in 0..slots_per_sector {
+            let offset = self.next_slot * RECORD_SIZE;
+            self.next_slot = (self.next_slot + 1) % slots;
+
+            if offset.is_multiple_of(SECTOR_SIZE) {
+                self.storage.erase_sector(offset)?;
+            } else {
+                self.storage.read(offset, &mut bytes)?;
+                if bytes.iter().any(|b| *b != 0xFF) {
+                    continue;
+                }
+            }
+            self.storage.write(offset, &record.to_bytes())?;
+            self.last = Some(record);
+            return Ok(());
+        }
+        Err(StorageError::OutOfBounds)
+    }
+}
+
+/// Opens the store and puts the saved distances into the vehicle state.
+/// Call it before the CAN tasks start, so the first frames already carry the old values.
+pub fn restore<S: Storage>(storage: S) -> Result<OdometerStore<S>, StorageError> {
+    let store = OdometerStore::open(storage)?;
+    match store.last() {
+        Some(record) => {
+            state::update(|s| {
+                s.total_distance = record.total_distance;
+                s.trips = record.trips;
+            });
+            info!("Odometer restored: {} m", record.total_distance);
+        }
+        None => info!("No saved odometer found"),
+    }
+    Ok(store)
+}
+
+/// Saves the distances (and the time with them) when they changed, once per odometer_save_s and at once after
+/// ignition off (the power may be cut any moment then).
+/// Never returns, the hardware adapters run it as a task.
+pub async fn odometer_loop<S: Storage>(mut store: OdometerStore<S>) {
+    info!("Odometer Task started");
+    let mut changes = state::receiver();
+    let mut saved = store.last().map(|r| (r.total_distance, r.trips));
+    let mut last_save = Instant::now();
+
+    loop {
+        wait_for_change(&mut changes, save_interval()).await;
+
+        let s = state::snapshot();
+        let current = (s.total_distance, s.trips);
+        if saved == Some(current) || (s.ignition && last_save.elapsed() < save_interval()) {
+            continue;
+        }
+
+        // the time of the clock, or the one we started it with again
+        let time = if time::clock_is_set() {
+            time::get_current_time_for_j1939().map(|dt| time::to_unix(&dt))
+        } else {
+            store.last().and_then(|r| r.time)
+        };
+        match store.save(current.0, current.1, time) {
+            Ok(()) => {
+                saved = Some(current);
+                last_save = Instant::now();
+            }
+            Err(e) => {
+                error!("Odometer not saved: {:?}", e);
+                last_save = Instant::now(); // we try again next interval
+            }
+        }
+    }
+}
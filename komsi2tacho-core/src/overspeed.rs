@@ -0,0 +1,246 @@
+//! Overspeed detection for the tachograph frame.
+//!
+//! The overspeed flag used to follow `speed > max_speed` directly, so it flickered with
+//! every frame around the limit. Now the speed has to stay above limit + tolerance
+//! (`overspeed_tolerance_kmh`) for `overspeed_min_s` seconds before the flag is set, like the
+//! 60 s rule of a real tacho. It is cleared again below limit + tolerance - hysteresis
+//! (`overspeed_hysteresis_kmh`). Without limit (KOMSI s0) there is no overspeed.
+//!
+//! Every overspeed is written to a small log that can be read over the serial port with
+//! command lines starting with ':':
+//!
+//! ```text
+//! overspeed          the last events, the oldest first
+//! overspeed clear
+//! ```
+
+use crate::config::{self, Config, Output, line};
+use crate::speed::Speed;
+use crate::state;
+use crate::time;
+use core::cell::RefCell;
+use core::fmt::Write as _;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Instant, Timer};
+use heapless::{Deque, String, Vec};
+
+/// How many events the log keeps, older ones are dropped
+pub const LOG_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OverspeedSettings {
+    pub tolerance_kmh: u32,
+    /// the speed has to be above the limit this long
+    pub min_duration_ms: u64,
+    pub hysteresis_kmh: u32,
+}
+
+impl OverspeedSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            tolerance_kmh: config.overspeed_tolerance_kmh.max(0) as u32,
+            min_duration_ms: config.overspeed_min_s.max(0) as u64 * 1000,
+            hysteresis_kmh: config.overspeed_hysteresis_kmh.max(0) as u32,
+        }
+    }
+}
+
+/// One overspeed, from the moment the speed went above the limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OverspeedEvent {
+    /// software clock in seconds since 1970, None if the clock was not set
+    pub start: Option<i64>,
+    pub duration_ms: u64,
+    /// the highest speed during the event
+    pub max_speed: Speed,
+    /// the limit in km/h when the event started
+    pub limit_kmh: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverspeedDetector {
+    /// the flag for the tacho
+    active: bool,
+    /// above the limit since then, the event starts when the minimum duration is over
+    above: Option<(Instant, OverspeedEvent)>,
+}
+
+impl OverspeedDetector {
+    pub const fn new() -> Self {
+        Self {
+            active: false,
+            above: None,
+        }
+    }
+
+    /// Checks the speed against the limit (km/h, 0 = none). `clock` is the software clock
+    /// for the log. Returns the event when an overspeed is over.
+    pub fn step(
+        &mut self,
+        speed: Speed,
+        limit_kmh: u32,
+        now: Instant,
+        clock: Option<i64>,
+        settings: &OverspeedSettings,
+    ) -> Option<OverspeedEvent> {
+        let threshold = limit_kmh.saturating_add(settings.tolerance_kmh);
+        let release = threshold.saturating_sub(settings.hysteresis_kmh);
+        let above = limit_kmh > 0 && speed > Speed::from_kmh(threshold);
+        let below = limit_kmh == 0 || speed <= Speed::from_kmh(release);
+
+        if let Some((since, event)) = &mut self.above {
+            event.max_speed = event.max_speed.max(speed);
+            event.duration_ms = now.saturating_duration_since(*since).as_millis();
+        }
+
+        if self.active {
+            if below {
+                self.active = false;
+                return self.above.take().map(|(_, event)| event);
+            }
+        } else if above {
+            let (_, event) = self.above.get_or_insert((
+                now,
+                OverspeedEvent {
+                    start: clock,
+                    duration_ms: 0,
+                    max_speed: speed,
+                    limit_kmh,
+                },
+            ));
+            self.active = event.duration_ms >= settings.min_duration_ms;
+        } else {
+            // not long enough, no event
+            self.above = None;
+        }
+        None
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// The overspeed going on, None if the flag is not set
+    pub fn current(&self) -> Option<OverspeedEvent> {
+        self.above.filter(|_| self.active).map(|(_, event)| event)
+    }
+}
+
+impl Default for OverspeedDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Overspeed {
+    detector: OverspeedDetector,
+    log: Deque<OverspeedEvent, LOG_SIZE>,
+}
+
+static OVERSPEED: Mutex<CriticalSectionRawMutex, RefCell<Overspeed>> =
+    Mutex::new(RefCell::new(Overspeed {
+        detector: OverspeedDetector::new(),
+        log: Deque::new(),
+    }));
+
+/// Checks the current speed and limit and sets the overspeed flag of the vehicle state.
+/// Called with every new speed or limit and regularly by [`overspeed_loop`].
+pub fn check() {
+    let s = state::snapshot();
+    let settings = OverspeedSettings::from_config(&config::get());
+    let clock = time::get_current_time_for_j1939().map(|dt| time::to_unix(&dt));
+    let active = OVERSPEED.lock(|o| {
+        let mut o = o.borrow_mut();
+        let ended = o
+            .detector
+            .step(s.speed, s.max_speed, Instant::now(), clock, &settings);
+        if let Some(event) = ended {
+            info!("Overspeed over: {:?}", event);
+            if o.log.is_full() {
+                o.log.pop_front();
+            }
+            let _ = o.log.push_back(event);
+        }
+        o.detector.is_active()
+    });
+    state::update(|s| s.overspeed = active);
+}
+
+/// The logged events, the oldest first
+pub fn events() -> Vec<OverspeedEvent, LOG_SIZE> {
+    OVERSPEED.lock(|o| o.borrow().log.iter().copied().collect())
+}
+
+pub fn clear_events() {
+    OVERSPEED.lock(|o| o.borrow_mut().log.clear());
+}
+
+fn show_event(out: &mut Output, number: &str, event: &OverspeedEvent) {
+    let secs = event.duration_ms / 1000;
+    let (max, limit) = (event.max_speed.kmh(), event.limit_kmh);
+    match event.start.map(time::from_unix) {
+        Some(t) => line(
+            out,
+            format_args!(
+                "{} {:04}-{:02}-{:02} {:02}:{:02}:{:02} {} s, max {} km/h, limit {}",
+                number, t.year, t.month, t.day, t.hour, t.min, t.sec, secs, max, limit
+            ),
+        ),
+        None => line(
+            out,
+            format_args!(
+                "{} no time, {} s, max {} km/h, limit {}",
+                number, secs, max, limit
+            ),
+        ),
+    }
+}
+
+/// Runs an overspeed command line (without the leading ':')
+pub fn run_command(command: &str) -> Output {
+    let mut out = Output::new();
+    let mut words = command.split_whitespace().skip(1);
+
+    match (words.next(), words.next()) {
+        (None, _) => {
+            let (log, current) = OVERSPEED.lock(|o| {
+                let o = o.borrow();
+                (
+                    o.log.iter().copied().collect::<Vec<_, LOG_SIZE>>(),
+                    o.detector.current(),
+                )
+            });
+            line(&mut out, format_args!("overspeed events: {}", log.len()));
+            for (i, event) in log.iter().enumerate() {
+                let mut number: String<8> = String::new();
+                let _ = write!(number, "{}:", i + 1);
+                show_event(&mut out, &number, event);
+            }
+            if let Some(event) = current {
+                show_event(&mut out, "now:", &event);
+            }
+        }
+        (Some("clear"), None) => {
+            clear_events();
+            line(&mut out, format_args!("OK: overspeed events cleared"));
+        }
+        _ => line(
+            &mut out,
+            format_args!("ERR: use overspeed or overspeed clear"),
+        ),
+    }
+    out
+}
+
+/// Checks regularly, so the flag comes after the minimum duration also without new speed.
+/// Never returns, the hardware adapters run it as a task.
+pub async fn overspeed_loop() {
+    info!("Overspeed Task started");
+    loop {
+        check();
+        Timer::after_millis(100).await;
+    }
+}
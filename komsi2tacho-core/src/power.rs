@@ -0,0 +1,169 @@
+//! Ignition and power state.
+//!
+//! Like in a real bus the J1939 broadcast follows the ignition: after ignition on the PGNs
+//! start one after the other, after ignition off we keep sending (speed 0) for a run-on time
+//! and then the bus becomes quiet.
+
+use crate::config;
+use crate::state;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::watch::Watch;
+use embassy_time::{Duration, Instant};
+
+/// Groups of cyclic messages that are switched on and off together
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Broadcast {
+    TimeDate,
+    Distance,
+    Engine,
+    Tachograph,
+}
+
+impl Broadcast {
+    const fn bit(self) -> u8 {
+        match self {
+            Broadcast::TimeDate => 0x01,
+            Broadcast::Distance => 0x02,
+            Broadcast::Tachograph => 0x04,
+            Broadcast::Engine => 0x08,
+        }
+    }
+}
+
+/// Order and delay after ignition on in which the broadcasts start
+pub const START_SEQUENCE: [(Broadcast, Duration); 4] = [
+    (Broadcast::TimeDate, Duration::from_millis(0)),
+    (Broadcast::Distance, Duration::from_millis(100)),
+    // the engine runs before the tacho sees any motion
+    (Broadcast::Engine, Duration::from_millis(150)),
+    (Broadcast::Tachograph, Duration::from_millis(200)),
+];
+
+const ALL_BROADCASTS: u8 = 0x0F;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerState {
+    /// ignition off and run-on time over, nothing is sent
+    Off,
+    /// ignition on, the broadcasts are started one after the other
+    Starting { since: Instant },
+    /// everything is sent
+    Running,
+    /// ignition off, we still send until the run-on time is over
+    RunOn { since: Instant },
+}
+
+impl PowerState {
+    /// Next state for the given ignition
+    pub fn next(self, ignition: bool, now: Instant, run_on: Duration) -> PowerState {
+        match (self, ignition) {
+            (PowerState::Off, true) => PowerState::Starting { since: now },
+            (PowerState::Off, false) => PowerState::Off,
+            (PowerState::Starting { since }, true) => {
+                let (_, last_delay) = START_SEQUENCE[START_SEQUENCE.len() - 1];
+                if now.saturating_duration_since(since) >= last_delay {
+                    PowerState::Running
+                } else {
+                    self
+                }
+            }
+            (PowerState::Starting { .. } | PowerState::Running, false) => {
+                PowerState::RunOn { since: now }
+            }
+            (PowerState::Running, true) => PowerState::Running,
+            // key turned back before the run-on time is over, nothing was switched off
+            (PowerState::RunOn { .. }, true) => PowerState::Running,
+            (PowerState::RunOn { since }, false) => {
+                if now.saturating_duration_since(since) >= run_on {
+                    PowerState::Off
+                } else {
+                    self
+                }
+            }
+        }
+    }
+
+    /// Bit mask of the broadcasts that are active in this state
+    pub fn broadcasts(self, now: Instant) -> u8 {
+        match self {
+            PowerState::Off => 0,
+            PowerState::Starting { since } => {
+                let elapsed = now.saturating_duration_since(since);
+                START_SEQUENCE
+                    .iter()
+                    .filter(|(_, delay)| elapsed >= *delay)
+                    .fold(0, |mask, (b, _)| mask | b.bit())
+            }
+            PowerState::Running | PowerState::RunOn { .. } => ALL_BROADCASTS,
+        }
+    }
+
+    /// True while the state changes by itself (without ignition change)
+    fn is_transient(self) -> bool {
+        matches!(self, PowerState::Starting { .. } | PowerState::RunOn { .. })
+    }
+}
+
+/// How long we keep sending after ignition off
+pub fn run_on_time() -> Duration {
+    Duration::from_millis(config::get().run_on_ms as u64)
+}
+
+// active broadcasts as bit mask, see Broadcast
+static BROADCASTS: Watch<CriticalSectionRawMutex, u8, 8> = Watch::new_with(0);
+
+pub fn is_enabled(broadcast: Broadcast) -> bool {
+    BROADCASTS.try_get().unwrap_or(0) & broadcast.bit() != 0
+}
+
+/// Waits until the broadcast is switched on, returns at once if it is already on
+pub async fn wait_enabled(broadcast: Broadcast) {
+    if is_enabled(broadcast) {
+        return;
+    }
+    match BROADCASTS.receiver() {
+        Some(mut rx) => {
+            rx.get_and(|mask| mask & broadcast.bit() != 0).await;
+        }
+        // all receivers taken, we poll
+        None => {
+            while !is_enabled(broadcast) {
+                embassy_time::Timer::after_millis(20).await;
+            }
+        }
+    }
+}
+
+/// Follows the ignition and switches the broadcasts on and off.
+/// Never returns, the hardware adapters run it as a task.
+pub async fn power_loop() {
+    info!("Power Task started");
+    let mut changes = state::receiver();
+    let mut power = PowerState::Off;
+
+    loop {
+        let now = Instant::now();
+        let next = power.next(state::snapshot().ignition, now, run_on_time());
+        if next != power {
+            info!("Power state: {:?}", next);
+            power = next;
+        }
+
+        let mask = power.broadcasts(now);
+        BROADCASTS.sender().send_if_modified(|current| {
+            let modified = *current != Some(mask);
+            *current = Some(mask);
+            modified
+        });
+
+        // while starting and in run-on the state changes by itself
+        let period = if power.is_transient() {
+            Duration::from_millis(20)
+        } else {
+            Duration::from_secs(1)
+        };
+        state::wait_for_change(&mut changes, period).await;
+    }
+}
@@ -0,0 +1,135 @@
+//! Speed ramp for the tachograph needle.
+//!
+//! Simulators send the speed only 5-10 times per second, the tachograph frame goes out every
+//! 45 ms. Without filter the MTCO needle jumps to every new value. With the ramp the sent
+//! speed moves linearly from the shown value to the new KOMSI value within the latency budget
+//! (`speed_ramp_ms`), but never faster than the maximum acceleration (`speed_ramp_accel`),
+//! like the needle of a real vehicle.
+//!
+//! Both 0 switches the ramp off, the KOMSI speed is sent as it is. Only the tachograph frame
+//! uses the ramp, the distance is still calculated from the KOMSI speed.
+
+use crate::config::{self, Config};
+use crate::speed::{SPEED_SCALE, Speed};
+use crate::state::{self, VehicleState};
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RampSettings {
+    /// km/h per second, 0 = no limit
+    pub max_acceleration: u32,
+    /// a new value is reached after this time (if the acceleration allows), 0 = at once
+    pub latency_ms: u32,
+}
+
+impl RampSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_acceleration: config.speed_ramp_accel.max(0) as u32,
+            latency_ms: config.speed_ramp_ms.max(0) as u32,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_acceleration > 0 || self.latency_ms > 0
+    }
+}
+
+/// Slew rate limiter between the KOMSI speed and the sent speed, all values in 1/1000 of
+/// the [`Speed`] resolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpeedRamp {
+    output: u64,
+    target: u64,
+    /// per second, fixed when a new target arrives
+    rate: u64,
+    last_step: Option<Instant>,
+}
+
+impl SpeedRamp {
+    /// starts with the vehicle standing
+    pub const fn new() -> Self {
+        Self {
+            output: 0,
+            target: 0,
+            rate: 0,
+            last_step: None,
+        }
+    }
+
+    /// Moves the output towards `target` for the time since the last step and returns
+    /// the speed to send
+    pub fn step(&mut self, target: Speed, now: Instant, settings: &RampSettings) -> Speed {
+        let target = target.raw() as u64 * 1000;
+        if !settings.is_enabled() {
+            *self = Self {
+                output: target,
+                target,
+                rate: 0,
+                last_step: Some(now),
+            };
+            return self.speed();
+        }
+
+        let elapsed_ms = self
+            .last_step
+            .map_or(0, |last| now.saturating_duration_since(last).as_millis());
+        self.last_step = Some(now);
+        self.advance(elapsed_ms);
+
+        if target != self.target {
+            self.target = target;
+            let distance = self.target.abs_diff(self.output);
+            let mut rate = match settings.latency_ms {
+                0 => u64::MAX,
+                latency => distance * 1000 / latency as u64,
+            };
+            if settings.max_acceleration > 0 {
+                rate = rate.min(settings.max_acceleration as u64 * SPEED_SCALE as u64 * 1000);
+            }
+            // at least one step per second, so we get there in any case
+            self.rate = rate.max(1);
+        }
+        self.speed()
+    }
+
+    fn advance(&mut self, elapsed_ms: u64) {
+        let step = self.rate.saturating_mul(elapsed_ms) / 1000;
+        self.output = if self.output < self.target {
+            self.output.saturating_add(step).min(self.target)
+        } else {
+            self.output.saturating_sub(step).max(self.target)
+        };
+    }
+
+    /// the current output
+    pub fn speed(&self) -> Speed {
+        Speed::from_raw(((self.output + 500) / 1000) as u32)
+    }
+
+    /// true if the output has reached the last target
+    pub fn is_settled(&self) -> bool {
+        self.output == self.target
+    }
+}
+
+impl Default for SpeedRamp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static RAMP: Mutex<CriticalSectionRawMutex, RefCell<SpeedRamp>> =
+    Mutex::new(RefCell::new(SpeedRamp::new()));
+
+/// The vehicle state as the tachograph should show it, with the speed from the ramp
+pub fn tacho_snapshot() -> VehicleState {
+    let mut state = state::snapshot();
+    let settings = RampSettings::from_config(&config::get());
+    state.speed = RAMP.lock(|r| r.borrow_mut().step(state.speed, Instant::now(), &settings));
+    state
+}
@@ -0,0 +1,205 @@
+//! Reconciliation of our odometer with the odometer of the simulator.
+//!
+//! The simulator is the authority, but setting its value directly makes the odometer jump
+//! and destroys the trip. With reconciliation (`odometer_reconcile 1`) the difference
+//! (drift) is corrected slowly instead:
+//!
+//! - small drift (up to `odometer_jump_m`) is slewed with at most `odometer_slew_m_s`.
+//!   If we are ahead, the odometer pauses while driving until the simulator has caught up,
+//!   it never runs backwards.
+//! - a big step forward (e.g. first value after start) is taken at once.
+//! - a big step backwards is rejected, `:odometer force` takes it anyway.
+//!
+//! The trips count what we really drove and are never touched. With the ignition off the
+//! drift waits, so the odometer is not saved again and again while it is slewed.
+
+use crate::config::{self, Config, Output};
+use crate::state::{self, VehicleState};
+use crate::trip::{self, TRIP_COUNT};
+use core::cell::Cell;
+use core::fmt::Write as _;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use heapless::String;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReconcileSettings {
+    /// false = the simulator value is set at once and the trips are reset (old behaviour)
+    pub enabled: bool,
+    /// meters per second
+    pub slew_rate: u32,
+    /// bigger drift is not slewed
+    pub jump_limit: u32,
+}
+
+impl ReconcileSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            enabled: config.odometer_reconcile,
+            slew_rate: config.odometer_slew_m_s.max(0) as u32,
+            jump_limit: config.odometer_jump_m.max(0) as u32,
+        }
+    }
+}
+
+/// What happened with an odometer value from the simulator, with the drift in meters
+/// (simulator - ours)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Reconciled {
+    /// reconciliation is off, the value was set and the trips reset
+    Set,
+    InSync,
+    Slewing(i64),
+    Jumped(i64),
+    Rejected(i64),
+}
+
+/// Handles an odometer value (meters) from the simulator
+pub fn reconcile(s: &mut VehicleState, simulator: u64, settings: &ReconcileSettings) -> Reconciled {
+    if !settings.enabled {
+        s.total_distance = simulator;
+        s.trips = [0; TRIP_COUNT];
+        s.distance_remainder = 0;
+        s.odometer_correction = 0;
+        return Reconciled::Set;
+    }
+
+    let drift = simulator as i64 - s.total_distance as i64;
+    if drift == 0 {
+        s.odometer_correction = 0;
+        Reconciled::InSync
+    } else if drift.unsigned_abs() <= settings.jump_limit as u64 {
+        s.odometer_correction = drift;
+        Reconciled::Slewing(drift)
+    } else if drift > 0 {
+        s.total_distance = simulator;
+        s.odometer_correction = 0;
+        Reconciled::Jumped(drift)
+    } else {
+        Reconciled::Rejected(drift)
+    }
+}
+
+/// Takes the simulator value even if it goes backwards, the trips stay
+pub fn force(s: &mut VehicleState, simulator: u64) {
+    s.total_distance = simulator;
+    s.odometer_correction = 0;
+}
+
+/// Applies the pending correction bit by bit while the distance is integrated
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Slew {
+    /// meters × µs per second we may still correct
+    budget: u64,
+}
+
+impl Slew {
+    pub const fn new() -> Self {
+        Self { budget: 0 }
+    }
+
+    /// Corrects the odometer after a step of `elapsed_us` in which `driven` meters were
+    /// added, returns the meters corrected (negative = held back)
+    pub fn step(&mut self, s: &mut VehicleState, driven: u64, elapsed_us: u64, rate: u32) -> i64 {
+        if s.odometer_correction == 0 || !s.ignition {
+            // no saving up for the next correction
+            self.budget = 0;
+            return 0;
+        }
+        // at most one second saved up, standing still does not allow a burst later
+        let per_second = rate as u64 * 1_000_000;
+        self.budget = self
+            .budget
+            .saturating_add(elapsed_us.saturating_mul(rate as u64))
+            .min(per_second);
+        let allowed = self.budget / 1_000_000;
+
+        let corrected = if s.odometer_correction > 0 {
+            let c = allowed.min(s.odometer_correction as u64);
+            s.total_distance = s.total_distance.saturating_add(c);
+            c as i64
+        } else {
+            // we can only hold back what was just driven
+            let c = allowed
+                .min(s.odometer_correction.unsigned_abs())
+                .min(driven);
+            s.total_distance -= c;
+            -(c as i64)
+        };
+        self.budget -= corrected.unsigned_abs() * 1_000_000;
+        s.odometer_correction -= corrected;
+        corrected
+    }
+}
+
+// the last value that was rejected, for `odometer force`
+static REJECTED: Mutex<CriticalSectionRawMutex, Cell<Option<u64>>> = Mutex::new(Cell::new(None));
+
+/// Handles an odometer value from KOMSI and reports the drift over USB
+pub fn simulator_odometer(simulator: u64) -> Reconciled {
+    let settings = ReconcileSettings::from_config(&config::get());
+    let result = Cell::new(Reconciled::InSync);
+    state::update(|s| result.set(reconcile(s, simulator, &settings)));
+    let result = result.get();
+    REJECTED.lock(|r| {
+        r.set(match result {
+            Reconciled::Rejected(_) => Some(simulator),
+            _ => None,
+        })
+    });
+
+    let mut msg: String<64> = String::new();
+    let _ = match result {
+        Reconciled::Set | Reconciled::InSync => Ok(()),
+        Reconciled::Slewing(drift) => write!(msg, "ODO: drift {:+} m, correcting", drift),
+        Reconciled::Jumped(drift) => write!(msg, "ODO: drift {:+} m, taken at once", drift),
+        Reconciled::Rejected(drift) => {
+            write!(msg, "ODO: drift {:+} m rejected, :odometer force", drift)
+        }
+    };
+    if matches!(result, Reconciled::Set | Reconciled::Jumped(_)) {
+        crate::distance::distance_set();
+    }
+    if !msg.is_empty() {
+        info!("{}", msg.as_str());
+        crate::commands::usb_write_dynamic(msg);
+    }
+    result
+}
+
+/// Serial command (after ':'):
+///
+/// ```text
+/// odometer         odometer, sent trip and pending correction
+/// odometer force   takes the last rejected simulator value
+/// ```
+pub fn run_command(command: &str) -> Output {
+    let mut out = Output::new();
+    let mut line: String<64> = String::new();
+    let mut words = command.split_whitespace().skip(1);
+    let _ = match (words.next(), words.next()) {
+        (None, _) => {
+            let s = state::snapshot();
+            write!(
+                line,
+                "odometer = {} m, trip = {} m, drift = {:+} m",
+                s.total_distance,
+                trip::selected_distance(&s),
+                s.odometer_correction
+            )
+        }
+        (Some("force"), None) => match REJECTED.lock(|r| r.take()) {
+            Some(simulator) => {
+                state::update(|s| force(s, simulator));
+                crate::distance::distance_set();
+                write!(line, "OK: odometer = {} m", simulator)
+            }
+            None => write!(line, "ERR: no rejected odometer value"),
+        },
+        _ => write!(line, "ERR: use odometer or odometer force"),
+    };
+    let _ = out.push(line);
+    out
+}
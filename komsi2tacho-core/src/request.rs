@@ -0,0 +1,127 @@
+//! Answers to the J1939 Request PGN (59904).
+//!
+//! Tachographs and clusters ask for some PGNs instead of waiting for the broadcast. We answer
+//! every PGN we transmit under our claimed address. Requests sent only to us for anything else
+//! get a NACK, requests to everybody are just ignored (J1939-21 does not allow a NACK for those).
+
+use crate::address::{AddressClaim, GLOBAL_ADDRESS, PGN_ADDRESS_CLAIMED, PGN_REQUEST};
+use crate::can::{
+    build_etc2_frame, build_hr_distance_frame, build_tachograph_frame, current_date_time_frame,
+};
+use crate::frame::CanFrame;
+use crate::mapping::{PGN_ETC2, PGN_HR_DISTANCE, PGN_TACHOGRAPH, PGN_TIME_DATE};
+use crate::ramp;
+use crate::state;
+use embassy_time::Instant;
+use heapless::Vec;
+use j1939::spn::{AcknowledgmentMessage, AcknowledgmentType};
+use j1939::{IdBuilder, PGN};
+
+pub const PGN_SOFTWARE_ID: u32 = 65242;
+// these need more than 8 bytes, and we have no transport protocol, so they get a NACK
+pub const PGN_VEHICLE_ID: u32 = 65260;
+pub const PGN_COMPONENT_ID: u32 = 65259;
+
+// builds the answer, None = right now we have nothing to send (e.g. time not set)
+type Responder = fn() -> Option<CanFrame>;
+
+// PGNs we can answer, all of them are sent from our claimed address
+const RESPONDERS: &[(u32, Responder)] = &[
+    (PGN_TACHOGRAPH, || {
+        Some(build_tachograph_frame(&ramp::tacho_snapshot()))
+    }),
+    (PGN_HR_DISTANCE, || {
+        Some(build_hr_distance_frame(&state::snapshot()))
+    }),
+    (PGN_TIME_DATE, current_date_time_frame),
+    (PGN_ETC2, || Some(build_etc2_frame(&state::snapshot()))),
+    (PGN_SOFTWARE_ID, build_software_id_frame),
+];
+
+/// The PGN asked for in a Request frame, None if it is no Request
+pub fn requested_pgn(frame: &CanFrame) -> Option<u32> {
+    if frame.pgn() != PGN_REQUEST || frame.data().len() < 3 {
+        return None;
+    }
+    let d = frame.data();
+    Some(u32::from_le_bytes([d[0], d[1], d[2], 0]))
+}
+
+/// Our answer to a Request frame, None if we keep quiet
+pub fn handle_request(frame: &CanFrame, claim: &AddressClaim, now: Instant) -> Option<CanFrame> {
+    let requested = requested_pgn(frame)?;
+    let da = frame.destination_address();
+    if da != GLOBAL_ADDRESS && Some(da) != claim.address() {
+        return None;
+    }
+
+    // the only answer allowed without an address ("cannot claim")
+    if requested == PGN_ADDRESS_CLAIMED {
+        return Some(claim.claim_frame());
+    }
+    if !claim.may_send(now) {
+        return None;
+    }
+
+    let response = RESPONDERS
+        .iter()
+        .find(|(pgn, _)| *pgn == requested)
+        .and_then(|(_, respond)| respond())
+        // we never answer for another ECU
+        .filter(|answer| Some(answer.source_address()) == claim.address());
+    match response {
+        Some(frame) => Some(frame),
+        None if da == GLOBAL_ADDRESS => None,
+        None => {
+            info!("Request for PGN {} not supported, NACK", requested);
+            Some(build_nack_frame(
+                requested,
+                frame.source_address(),
+                claim.address()?,
+            ))
+        }
+    }
+}
+
+/// Negative acknowledgment for a request from `requester`
+pub fn build_nack_frame(pgn: u32, requester: u8, source_address: u8) -> CanFrame {
+    // PGN: Acknowledgment (0xE800 = 59392), always to global
+    let id = IdBuilder::from_pgn(PGN::AcknowledgmentMessage)
+        .priority(6)
+        .da(GLOBAL_ADDRESS)
+        .sa(source_address)
+        .build();
+
+    let msg = AcknowledgmentMessage {
+        control_byte: Some(AcknowledgmentType::Negative),
+        group_function_value: 0xFF,
+        pgn: PGN::from(pgn),
+    };
+
+    // byte 5 is the address of the requester (J1939-21 since 2006), the crate leaves it at 0xFF
+    let mut data = msg.to_pdu();
+    data[4] = requester;
+    CanFrame::new(id.as_raw(), &data)
+}
+
+/// Software identification: number of fields, then the version ended with '*'.
+/// Without transport protocol only 6 characters fit, so we send the version without
+/// pre-release part ("1.8.0" for "1.8.0-beta").
+pub fn build_software_id_frame() -> Option<CanFrame> {
+    let id = IdBuilder::from_pgn(PGN::from(PGN_SOFTWARE_ID))
+        .priority(6)
+        .sa(crate::address::source_address())
+        .build();
+
+    let version = env!("CARGO_PKG_VERSION");
+    let version = version.split(['-', '+']).next().unwrap_or(version);
+
+    let mut data: Vec<u8, 8> = Vec::new();
+    data.push(1).ok()?;
+    data.extend_from_slice(version.as_bytes()).ok()?;
+    data.push(b'*').ok()?;
+    // unused bytes are 0xFF
+    while data.push(0xFF).is_ok() {}
+
+    Some(CanFrame::new(id.as_raw(), &data))
+}
@@ -0,0 +1,87 @@
+//! Tachograph output shaft speed (SPN 1623).
+//!
+//! The MTCO compares the shaft speed with the vehicle speed. If they don't fit its
+//! calibration, it assumes manipulation and shows a fault, so the shaft speed has to come
+//! from the same constants the tacho was calibrated with:
+//!
+//! - k: impulses per km the tacho expects (on the installation plaque)
+//! - gear/axle ratio: output shaft revolutions per wheel revolution
+//! - tyre circumference l in mm
+//!
+//! With ratio and circumference the shaft turns `ratio * 1_000_000 / l` times per km and
+//! k is not used. Without them (0) we take k and `impulses_per_revolution`.
+//!
+//! `impulses_per_revolution` is 1 by default, which gives the shaft speed of the old
+//! `shaft_factor` 1333 (133.3 rpm per km/h) with the default k of 8000. A KITAS sensor
+//! mostly has 8.
+
+use crate::config::Config;
+use crate::speed::{SPEED_SCALE, Speed};
+
+// 0.125 rpm per bit, highest valid value of SPN 1623 (0xFAFF)
+pub const MAX_SHAFT_SPEED: u16 = 8031;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ShaftModel {
+    /// impulses per km
+    pub k_factor: u32,
+    /// gear/axle ratio in 1/1000, 0 = unknown
+    pub axle_ratio_milli: u32,
+    /// 0 = unknown
+    pub tyre_circumference_mm: u32,
+    /// impulses of the sensor per shaft revolution, used if ratio or tyre are unknown
+    pub impulses_per_revolution: u32,
+}
+
+impl ShaftModel {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            k_factor: config.k_factor.max(0) as u32,
+            axle_ratio_milli: config.axle_ratio_milli.max(0) as u32,
+            tyre_circumference_mm: config.tyre_circumference_mm.max(0) as u32,
+            impulses_per_revolution: config.impulses_per_revolution.max(1) as u32,
+        }
+    }
+
+    /// False if ratio and tyre are set, then k does not change the shaft speed
+    pub fn uses_k_factor(&self) -> bool {
+        self.axle_ratio_milli == 0 || self.tyre_circumference_mm == 0
+    }
+
+    /// Output shaft revolutions per km in 1/1000
+    pub fn revolutions_per_km_milli(&self) -> u64 {
+        if self.uses_k_factor() {
+            self.k_factor as u64 * 1000 / self.impulses_per_revolution.max(1) as u64
+        } else {
+            // ratio/1000 * 1_000_000 mm / l, and that in 1/1000
+            self.axle_ratio_milli as u64 * 1_000_000 / self.tyre_circumference_mm as u64
+        }
+    }
+
+    /// Impulses per shaft revolution in 1/1000. With ratio and tyre this tells if they fit k,
+    /// a real sensor has a whole number.
+    pub fn impulses_per_revolution_milli(&self) -> u64 {
+        match self.revolutions_per_km_milli() {
+            0 => 0,
+            revolutions => self.k_factor as u64 * 1_000_000 / revolutions,
+        }
+    }
+
+    /// Shaft speed in rpm for a vehicle speed, rounded and limited to the highest
+    /// value J1939 allows. It is never 0 while the vehicle moves.
+    pub fn shaft_speed(&self, speed: Speed) -> u16 {
+        if !speed.is_moving() {
+            return 0;
+        }
+        // km/h * rev/km / 60 min/h, the 1/256 km/h and 1/1000 rev rounded away at the end
+        let divisor = SPEED_SCALE as u64 * 60 * 1000;
+        let rpm = (speed.raw() as u64 * self.revolutions_per_km_milli() + divisor / 2) / divisor;
+        rpm.clamp(1, MAX_SHAFT_SPEED as u64) as u16
+    }
+}
+
+/// Shaft speed with the current configuration
+pub fn shaft_speed(speed: Speed) -> u16 {
+    ShaftModel::from_config(&crate::config::get()).shaft_speed(speed)
+}
@@ -0,0 +1,84 @@
+//! Vehicle speed in fixed point.
+//!
+//! KOMSI sends whole km/h, but the tachograph frame has 1/256 km/h per bit. We keep the
+//! speed in this resolution, so slow manoeuvring (e.g. 3.5 km/h) reaches the tacho and the
+//! distance as it is. A decimal speed like `y3.5` is an extension of Komsi2Tacho.
+
+/// 1/256 km/h per bit, like SPN 1624 (tachograph vehicle speed)
+pub const SPEED_SCALE: u32 = 256;
+
+// highest valid value of SPN 1624 (0xFAFF = 250.996 km/h)
+const MAX_J1939_SPEED: u32 = 0xFAFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Speed(u32);
+
+impl Speed {
+    pub const ZERO: Speed = Speed(0);
+
+    pub const fn from_kmh(kmh: u32) -> Self {
+        Self(kmh.saturating_mul(SPEED_SCALE))
+    }
+
+    /// from 1/256 km/h
+    pub const fn from_raw(raw: u32) -> Self {
+        Self(raw)
+    }
+
+    /// in 1/256 km/h
+    pub const fn raw(self) -> u32 {
+        self.0
+    }
+
+    /// rounded to whole km/h
+    pub const fn kmh(self) -> u32 {
+        (self.0 + SPEED_SCALE / 2) / SPEED_SCALE
+    }
+
+    pub const fn is_moving(self) -> bool {
+        self.0 > 0
+    }
+
+    /// The value for the J1939 frame, limited to the highest valid value
+    pub fn to_j1939(self) -> u16 {
+        self.0.min(MAX_J1939_SPEED) as u16
+    }
+
+    /// Parses the digits of a KOMSI speed: whole km/h ("42") or with decimals ("3.5"),
+    /// rounded to the nearest 1/256 km/h. None if it is no number.
+    pub fn parse(digits: &[u8]) -> Option<Self> {
+        let mut parts = digits.splitn(2, |&b| b == b'.');
+        let whole = parts.next()?;
+        let fraction = parts.next().unwrap_or(&[]);
+        if whole.is_empty() && fraction.is_empty() {
+            return None;
+        }
+
+        let mut kmh: u32 = 0;
+        for &d in whole {
+            if !d.is_ascii_digit() {
+                return None;
+            }
+            kmh = kmh.checked_mul(10)?.checked_add((d - b'0') as u32)?;
+        }
+
+        // more than 6 decimals are far below 1/256 km/h
+        let mut numerator: u64 = 0;
+        let mut denominator: u64 = 1;
+        for (i, &d) in fraction.iter().enumerate() {
+            if !d.is_ascii_digit() {
+                return None;
+            }
+            if i < 6 {
+                numerator = numerator * 10 + (d - b'0') as u64;
+                denominator *= 10;
+            }
+        }
+        let fraction = (numerator * SPEED_SCALE as u64 + denominator / 2) / denominator;
+
+        Some(Self(
+            kmh.checked_mul(SPEED_SCALE)?.checked_add(fraction as u32)?,
+        ))
+    }
+}
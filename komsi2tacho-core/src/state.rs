@@ -0,0 +1,210 @@
+//! The vehicle state as we know it from the KOMSI commands.
+//!
+//! All values live in one struct behind a [`Watch`], so every CAN frame is built from one
+//! consistent snapshot and the senders can wait for changes instead of polling.
+
+use crate::speed::Speed;
+use crate::trip::TRIP_COUNT;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::watch::{Receiver, Watch};
+use embassy_time::{Duration, Instant, Timer};
+
+/// Working state of a driver, as shown by the tachograph
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WorkingState {
+    RestSleeping,
+    DriverAvailable,
+    Work,
+    Drive,
+}
+
+/// Time related state of a driver: the warnings of the tachograph before and at the limits
+/// of continuous driving (4½ h), daily driving (9 h) and the daily rest (16 h after the last)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TimeState {
+    Normal,
+    Before4h30,
+    Reached4h30,
+    Before9h,
+    Reached9h,
+    Before16h,
+    Reached16h,
+}
+
+/// Direction of travel from the gear lever (see [`crate::gear`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    Forward,
+    Neutral,
+    Reverse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DriverState {
+    /// None = "not available" on the bus
+    pub working_state: Option<WorkingState>,
+    /// the working state follows the vehicle instead (see [`crate::driver`])
+    pub auto: bool,
+    pub card_present: bool,
+    /// None = "not available" on the bus
+    pub time_state: Option<TimeState>,
+    /// the time state comes from the driving times (see [`crate::driving_time`])
+    pub time_auto: bool,
+}
+
+/// Injected tachograph events and faults for training (see [`crate::fault`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Faults {
+    /// the speed sensor fails
+    pub sensor: bool,
+    /// the tacho lost its power supply
+    pub power: bool,
+    /// someone is in the setup of the tacho
+    pub handling: bool,
+    pub performance: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct VehicleState {
+    /// actual speed
+    pub speed: Speed,
+    /// engine speed in rpm, 0 = engine stopped
+    pub engine_rpm: u32,
+    /// speed limit in km/h, 0 = no limit
+    pub max_speed: u32,
+    /// the overspeed flag for the tacho (see [`crate::overspeed`])
+    pub overspeed: bool,
+    /// odometer in meters
+    pub total_distance: u64,
+    /// trip distances in meters (see [`crate::trip`])
+    pub trips: [u64; TRIP_COUNT],
+    /// driven distance below one meter, not counted yet (see [`crate::distance`])
+    pub distance_remainder: u64,
+    /// meters the odometer still has to be corrected towards the simulator
+    /// (see [`crate::reconcile`])
+    pub odometer_correction: i64,
+    pub ignition: bool,
+    pub direction: Direction,
+    /// number of the forward or reverse gear, 1 if the simulator does not tell us
+    pub gear: u8,
+    pub driver1: DriverState,
+    pub driver2: DriverState,
+    pub faults: Faults,
+    /// when the last speed was received
+    pub speed_received: Option<Instant>,
+    /// when anything in the state changed last
+    pub last_change: Option<Instant>,
+}
+
+impl VehicleState {
+    pub const fn new() -> Self {
+        Self {
+            speed: Speed::ZERO,
+            engine_rpm: 0,
+            max_speed: 0,
+            overspeed: false,
+            total_distance: 0,
+            trips: [0; TRIP_COUNT],
+            distance_remainder: 0,
+            odometer_correction: 0,
+            // until KOMSI tells us otherwise the ignition is on, so setups without
+            // ignition command keep working
+            ignition: true,
+            direction: Direction::Forward,
+            gear: 1,
+            driver1: DriverState {
+                working_state: Some(WorkingState::Drive),
+                auto: true,
+                card_present: true,
+                time_state: None,
+                time_auto: true,
+            },
+            driver2: DriverState {
+                working_state: None,
+                auto: false,
+                card_present: false, // Better 'false' instead of 'None'
+                time_state: None,
+                time_auto: false,
+            },
+            faults: Faults {
+                sensor: false,
+                power: false,
+                handling: false,
+                performance: false,
+            },
+            speed_received: None,
+            last_change: None,
+        }
+    }
+}
+
+impl Default for VehicleState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Maximum number of tasks that wait for state changes
+pub const MAX_STATE_RECEIVERS: usize = 8;
+
+pub type StateReceiver =
+    Receiver<'static, CriticalSectionRawMutex, VehicleState, MAX_STATE_RECEIVERS>;
+
+static VEHICLE_STATE: Watch<CriticalSectionRawMutex, VehicleState, MAX_STATE_RECEIVERS> =
+    Watch::new_with(VehicleState::new());
+
+/// Returns a consistent copy of the whole vehicle state
+pub fn snapshot() -> VehicleState {
+    VEHICLE_STATE.try_get().unwrap_or_default()
+}
+
+/// Changes the vehicle state. The receivers are only notified if something really changed.
+pub fn update<F: Fn(&mut VehicleState)>(f: F) {
+    VEHICLE_STATE.sender().send_if_modified(|slot| {
+        let old = slot.unwrap_or_default();
+        let mut new = old;
+        f(&mut new);
+        if new == old {
+            return false;
+        }
+        new.last_change = Some(Instant::now());
+        *slot = Some(new);
+        true
+    });
+}
+
+/// Receiver for state change notifications, None if all receivers are taken
+pub fn receiver() -> Option<StateReceiver> {
+    VEHICLE_STATE.receiver()
+}
+
+/// Waits for the given time or until the vehicle state has changed, whatever comes first.
+/// Without receiver (all taken) we only wait for the time.
+pub async fn wait_for_change(changes: &mut Option<StateReceiver>, period: Duration) {
+    match changes {
+        Some(rx) => {
+            let _ = embassy_time::with_timeout(period, rx.changed()).await;
+        }
+        None => Timer::after(period).await,
+    }
+}
+
+/// Like [`wait_for_change`], but only changes for which `f` returns true count
+pub async fn wait_for_change_where(
+    changes: &mut Option<StateReceiver>,
+    period: Duration,
+    f: impl Fn(&VehicleState) -> bool,
+) {
+    match changes {
+        Some(rx) => {
+            let _ = embassy_time::with_timeout(period, rx.changed_and(f)).await;
+        }
+        None => Timer::after(period).await,
+    }
+}
@@ -0,0 +1,238 @@
+//! Software clock for the TimeDate frame.
+//!
+//! KOMSI sets the date and time now and then, in between we count on from the last value.
+//! Simulators can run faster or slower than real time, so the clock runs with a time scale:
+//! fixed with `time_scale_milli` (1000 = real time), or with `time_scale_milli 0` measured
+//! from the KOMSI updates. With the right scale the clock does not jump back on each update.
+//! `time_scale_distance 1` also drives the distance with it (see [`crate::distance`]).
+//!
+//! After a start the clock counts on from the time saved with the odometer, or from
+//! `fallback_date` (YYYYMMDD). It is not set until KOMSI sends a time, and until then
+//! `time_unset_mode` says what the TimeDate frame gets: `silent` = nothing,
+//! `not_available` = "not available", `send` = the time we have.
+
+use crate::config;
+use crate::timezone;
+use embassy_time::Instant;
+use komsi::KomsiDateTime;
+
+// if we would use AtomicU32 instead of AtomicU64 we would not need "portable_atomic" crate,
+// but then we would get a timestamp overflow after 49,7 days. The simulation will probably
+// never run this long after a SetTime but I hate possible overflows
+
+/// Real time
+pub const REAL_TIME: u32 = 1000;
+
+// the measured scale stays in this range, 1/10 to 100 times real time
+const MIN_SCALE: u32 = 100;
+const MAX_SCALE: u32 = 100_000;
+
+// KOMSI sends whole seconds, so we measure over at least this long
+const MIN_MEASURE_MS: u64 = 10_000;
+
+/// Clock of the simulation, counts on from the last KOMSI time with a time scale
+#[derive(Debug, Clone, Copy)]
+pub struct SimClock {
+    // the last time from KOMSI (seconds since 1970) and when it came
+    base: Option<(i64, Instant)>,
+    // start of the current measurement
+    anchor: Option<(i64, Instant)>,
+    // measured scale in 1/1000
+    measured: u32,
+    // KOMSI has sent a time
+    set: bool,
+}
+
+impl SimClock {
+    pub const fn new() -> Self {
+        Self {
+            base: None,
+            anchor: None,
+            measured: REAL_TIME,
+            set: false,
+        }
+    }
+
+    /// Starts the clock with a time we only assume (saved or fallback), it is not set
+    pub fn start(&mut self, secs: i64, now: Instant) {
+        if !self.set {
+            self.base = Some((secs, now));
+        }
+    }
+
+    /// KOMSI has sent a time
+    pub fn is_set(&self) -> bool {
+        self.set
+    }
+
+    /// The scale in 1/1000: `fixed`, or the measured one if `fixed` is 0
+    pub fn scale(&self, fixed: u32) -> u32 {
+        if fixed > 0 { fixed } else { self.measured }
+    }
+
+    /// Sets the clock to a time (UTC) from KOMSI, and measures the scale between the updates
+    pub fn sync(&mut self, dt: &KomsiDateTime, now: Instant) {
+        let secs = to_unix(dt);
+        self.base = Some((secs, now));
+        self.set = true;
+
+        match self.anchor {
+            Some((then_secs, then)) => {
+                let real_ms = now.saturating_duration_since(then).as_millis();
+                let sim_secs = secs - then_secs;
+                if sim_secs <= 0 || real_ms > 3_600_000 {
+                    // the time was set back or we heard nothing for long, we start again
+                    self.anchor = Some((secs, now));
+                } else if real_ms >= MIN_MEASURE_MS {
+                    let scale = (sim_secs as u64 * 1_000_000 / real_ms)
+                        .clamp(MIN_SCALE as u64, MAX_SCALE as u64)
+                        as u32;
+                    // smoothed, one update with a rounded second does not move it much
+                    self.measured = (self.measured * 3 + scale) / 4;
+                    self.anchor = Some((secs, now));
+                }
+            }
+            None => self.anchor = Some((secs, now)),
+        }
+    }
+
+    /// The current time of the simulation, None until KOMSI has sent one
+    pub fn now(&self, now: Instant, scale: u32) -> Option<KomsiDateTime> {
+        let (secs, then) = self.base?;
+        let real_ms = now.saturating_duration_since(then).as_millis();
+        let sim_secs = real_ms.saturating_mul(scale as u64) / 1_000_000;
+        Some(from_unix(
+            secs.saturating_add(sim_secs.min(i64::MAX as u64) as i64),
+        ))
+    }
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static CLOCK: spin::RwLock<SimClock> = spin::RwLock::new(SimClock::new());
+
+/// The current time scale in 1/1000 (configured or measured)
+pub fn time_scale() -> u32 {
+    CLOCK
+        .read()
+        .scale(config::get().time_scale_milli.max(0) as u32)
+}
+
+/// Sets the clock with a time from KOMSI (local time is converted to UTC, see
+/// [`crate::timezone`])
+pub fn sync_system_time(dt: KomsiDateTime) {
+    let dt = timezone::komsi_to_utc(dt);
+    CLOCK.write().sync(&dt, Instant::now());
+    info!("DateTime set to: {:?} UTC", dt);
+}
+
+/// Starts the clock with the time saved with the odometer, or else with `fallback_date`.
+/// Without both it has no time until KOMSI sends one.
+pub fn start_clock(saved: Option<i64>) {
+    let start = saved.or_else(|| parse_date(config::get().fallback_date).map(|dt| to_unix(&dt)));
+    if let Some(secs) = start {
+        CLOCK.write().start(secs, Instant::now());
+        info!("Clock started with {:?} UTC, not set", from_unix(secs));
+    }
+}
+
+/// KOMSI has sent a time since the start
+pub fn clock_is_set() -> bool {
+    CLOCK.read().is_set()
+}
+
+/// 00:00:00 of a date as YYYYMMDD, None for 0 or an invalid date
+pub fn parse_date(date: i32) -> Option<KomsiDateTime> {
+    let (year, month, day) = (date / 10_000, (date / 100 % 100) as u8, (date % 100) as u8);
+    if !(1..=9999).contains(&year) || day == 0 || day > days_in_month(year as u16, month) {
+        return None;
+    }
+    Some(KomsiDateTime {
+        year: year as u16,
+        month,
+        day,
+        hour: 0,
+        min: 0,
+        sec: 0,
+    })
+}
+
+/// calculates current time (UTC) based on the last update
+pub fn get_current_time_for_j1939() -> Option<KomsiDateTime> {
+    let scale = time_scale();
+    CLOCK.read().now(Instant::now(), scale)
+}
+
+/// Adds seconds to a date and time, with month lengths, leap years and the year rollover
+pub fn add_seconds(dt: &mut KomsiDateTime, secs: u64) {
+    *dt = from_unix(to_unix(dt).saturating_add(secs.min(i64::MAX as u64) as i64));
+}
+
+/// Seconds since 1970-01-01 00:00:00
+pub fn to_unix(dt: &KomsiDateTime) -> i64 {
+    days_from_civil(dt.year, dt.month, dt.day) * 86_400
+        + dt.hour as i64 * 3600
+        + dt.min as i64 * 60
+        + dt.sec as i64
+}
+
+/// Date and time of the seconds since 1970-01-01 00:00:00
+pub fn from_unix(secs: i64) -> KomsiDateTime {
+    let secs_of_day = secs.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    KomsiDateTime {
+        year: year.clamp(0, u16::MAX as i64) as u16,
+        month,
+        day,
+        hour: (secs_of_day / 3600) as u8,
+        min: (secs_of_day / 60 % 60) as u8,
+        sec: (secs_of_day % 60) as u8,
+    }
+}
+
+pub const fn is_leap_year(year: u16) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+/// 0 for an invalid month
+pub const fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 0,
+    }
+}
+
+// Days since 1970-01-01, after Howard Hinnant's "days_from_civil". The year starts in
+// March, so the leap day is the last day of a year. An invalid month counts as January,
+// a day behind the end of the month runs into the next one.
+fn days_from_civil(year: u16, month: u8, day: u8) -> i64 {
+    let month = month.clamp(1, 12) as i64;
+    let year = year as i64 - if month <= 2 { 1 } else { 0 };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day.max(1) as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// (year, month, day) of the days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
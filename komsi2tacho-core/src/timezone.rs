@@ -0,0 +1,101 @@
+//! Time zone of the TimeDate frame.
+//!
+//! The tachograph works in UTC and the cluster shows local time, so the TimeDate frame has
+//! the time in UTC plus the local offset. We know the offset from the configuration:
+//!
+//! - `timezone_offset_min`: standard offset from UTC, e.g. 60 for CET, 330 for India
+//! - `timezone_dst`: 1 = European summer time (one hour more from the last Sunday in March
+//!   to the last Sunday in October, 01:00 UTC each), 0 = fixed offset
+//! - `komsi_local_time`: 1 = the simulator sends local time, we convert it to UTC
+//!
+//! With the defaults (all 0) KOMSI time is UTC and the offset fields are 0, like before.
+
+use crate::config::{self, Config};
+use crate::time::{days_in_month, from_unix, to_unix};
+use komsi::KomsiDateTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimeZone {
+    /// minutes east of UTC, without summer time
+    pub offset_min: i32,
+    pub eu_dst: bool,
+}
+
+impl TimeZone {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            offset_min: config.timezone_offset_min,
+            eu_dst: config.timezone_dst,
+        }
+    }
+
+    /// Minutes between UTC and local time at a moment in UTC
+    pub fn offset_at(&self, utc: &KomsiDateTime) -> i32 {
+        if self.eu_dst && is_eu_summer_time(utc) {
+            self.offset_min + 60
+        } else {
+            self.offset_min
+        }
+    }
+
+    pub fn to_local(&self, utc: &KomsiDateTime) -> KomsiDateTime {
+        from_unix(to_unix(utc) + self.offset_at(utc) as i64 * 60)
+    }
+
+    /// UTC of a local time. In the hour that exists twice in autumn we take summer time,
+    /// a time in the hour skipped in spring is taken as winter time.
+    pub fn to_utc(&self, local: &KomsiDateTime) -> KomsiDateTime {
+        let standard = to_unix(local) - self.offset_min as i64 * 60;
+        let summer = from_unix(standard - 3600);
+        if self.eu_dst && is_eu_summer_time(&summer) {
+            summer
+        } else {
+            from_unix(standard)
+        }
+    }
+}
+
+/// The offset as the (hour, minute) fields of the TimeDate frame, both with the same sign.
+/// The config range (-12 h..+14 h, plus summer time) keeps the hours far inside an i8.
+pub fn offset_fields(offset_min: i32) -> (i8, i8) {
+    ((offset_min / 60) as i8, (offset_min % 60) as i8)
+}
+
+/// Is European summer time at this moment (in UTC)?
+pub fn is_eu_summer_time(utc: &KomsiDateTime) -> bool {
+    let begin = last_sunday(utc.year, 3) + 3600;
+    let end = last_sunday(utc.year, 10) + 3600;
+    (begin..end).contains(&to_unix(utc))
+}
+
+// 00:00 UTC of the last Sunday of a month, in seconds since 1970
+fn last_sunday(year: u16, month: u8) -> i64 {
+    let last_day = KomsiDateTime {
+        year,
+        month,
+        day: days_in_month(year, month),
+        hour: 0,
+        min: 0,
+        sec: 0,
+    };
+    let days = to_unix(&last_day).div_euclid(86_400);
+    // 1970-01-01 was a Thursday
+    let weekday = (days + 4).rem_euclid(7);
+    (days - weekday) * 86_400
+}
+
+/// The time zone of the current configuration
+pub fn time_zone() -> TimeZone {
+    TimeZone::from_config(&config::get())
+}
+
+/// Time from KOMSI to UTC, if the simulator sends local time
+pub fn komsi_to_utc(dt: KomsiDateTime) -> KomsiDateTime {
+    let config = config::get();
+    if config.komsi_local_time {
+        TimeZone::from_config(&config).to_utc(&dt)
+    } else {
+        dt
+    }
+}
@@ -0,0 +1,130 @@
+//! Trip counters.
+//!
+//! We count several trips at the same time (e.g. trip 1 per shift, trip 2 per route), the
+//! HR distance frame carries the one selected with `trip_selected`. They can be queried,
+//! reset and set over the serial port with command lines starting with ':':
+//!
+//! ```text
+//! trip                  all trips, the sent one is marked
+//! trip reset            the sent trip
+//! trip reset <n>|all
+//! trip set <n> <m>      trip n to m meters
+//! trip select <n>       sends trip n from now on (saved in the config)
+//! ```
+//!
+//! KOMSI has no letter for them, a scenario can reset them with our extension. Selecting
+//! stays a serial command, it is a setting saved in the flash and no part of a scenario:
+//!
+//! ```text
+//! U   reset: 0 the sent trip, 1..TRIP_COUNT that trip, 9 all
+//! ```
+
+use crate::config::{self, Output, line};
+use crate::distance;
+use crate::state::{self, VehicleState};
+use core::fmt::Write as _;
+use heapless::String;
+
+pub const TRIP_COUNT: usize = 3;
+
+/// Index of the trip for the HR distance frame
+pub fn selected() -> usize {
+    (config::get().trip_selected.clamp(1, TRIP_COUNT as i32) - 1) as usize
+}
+
+/// The distance of the selected trip
+pub fn selected_distance(s: &VehicleState) -> u64 {
+    s.trips[selected()]
+}
+
+/// KOMSI U, other values are ignored
+pub fn reset_trips(s: &mut VehicleState, value: u32) {
+    match value as usize {
+        0 => s.trips[selected()] = 0,
+        n @ 1..=TRIP_COUNT => s.trips[n - 1] = 0,
+        9 => s.trips = [0; TRIP_COUNT],
+        _ => return,
+    }
+    // the tacho gets the new value at once
+    distance::distance_set();
+}
+
+// "1".."3" to the index
+fn parse_trip(word: &str) -> Option<usize> {
+    match word.parse::<usize>() {
+        Ok(n) if (1..=TRIP_COUNT).contains(&n) => Some(n - 1),
+        _ => None,
+    }
+}
+
+fn changed(out: &mut Output) {
+    // the tacho gets the new value at once
+    distance::distance_set();
+    let s = state::snapshot();
+    for (i, trip) in s.trips.iter().enumerate() {
+        line(out, format_args!("OK: trip {} = {} m", i + 1, trip));
+    }
+}
+
+/// Runs a trip command line (without the leading ':')
+pub fn run_command(command: &str) -> Output {
+    let mut out = Output::new();
+    let mut words = command.split_whitespace().skip(1);
+
+    match (words.next(), words.next(), words.next(), words.next()) {
+        (None, ..) => {
+            let s = state::snapshot();
+            let sent = selected();
+            for (i, trip) in s.trips.iter().enumerate() {
+                let mark = if i == sent { " (sent)" } else { "" };
+                line(
+                    &mut out,
+                    format_args!("trip {} = {} m{}", i + 1, trip, mark),
+                );
+            }
+        }
+        (Some("reset"), None, None, None) => {
+            let sent = selected();
+            state::update(|s| s.trips[sent] = 0);
+            changed(&mut out);
+        }
+        (Some("reset"), Some("all"), None, None) => {
+            state::update(|s| s.trips = [0; TRIP_COUNT]);
+            changed(&mut out);
+        }
+        (Some("reset"), Some(n), None, None) => match parse_trip(n) {
+            Some(i) => {
+                state::update(|s| s.trips[i] = 0);
+                changed(&mut out);
+            }
+            None => line(
+                &mut out,
+                format_args!("ERR: trip must be 1..{}", TRIP_COUNT),
+            ),
+        },
+        (Some("set"), Some(n), Some(meters), None) => {
+            match (parse_trip(n), meters.parse::<u64>()) {
+                (None, _) => line(
+                    &mut out,
+                    format_args!("ERR: trip must be 1..{}", TRIP_COUNT),
+                ),
+                (_, Err(_)) => line(&mut out, format_args!("ERR: not a number: {}", meters)),
+                (Some(i), Ok(m)) => {
+                    state::update(|s| s.trips[i] = m);
+                    changed(&mut out);
+                }
+            }
+        }
+        (Some("select"), Some(n), None, None) => {
+            let mut set: String<64> = String::new();
+            let _ = write!(set, "set trip_selected {}", n);
+            out = config::run_command(&set);
+            distance::distance_set();
+        }
+        _ => line(
+            &mut out,
+            format_args!("ERR: use trip, trip reset, trip set or trip select"),
+        ),
+    }
+    out
+}
@@ -0,0 +1,123 @@
+// Host tests for the J1939 NAME and address claim
+
+use embassy_time::Instant;
+use komsi2tacho_core::address::{
+    AddressClaim, DEFAULT_ADDRESS, NULL_ADDRESS, Name, identity_number_from_mac,
+};
+
+fn at(ms: u64) -> Instant {
+    Instant::from_millis(ms)
+}
+
+fn name(identity_number: u32, arbitrary_address_capable: bool) -> Name {
+    Name {
+        identity_number,
+        arbitrary_address_capable,
+        ..Name::new()
+    }
+}
+
+#[test]
+fn name_round_trip() {
+    let name = Name {
+        identity_number: 0x1F_FFFF,
+        manufacturer_code: 0x123,
+        ecu_instance: 5,
+        function_instance: 17,
+        function: 20,
+        vehicle_system: 0x55,
+        vehicle_system_instance: 9,
+        industry_group: 1,
+        arbitrary_address_capable: true,
+    };
+    assert_eq!(Name::from_u64(name.to_u64()), name);
+    assert_eq!(name.to_u64() >> 63, 1);
+    assert_eq!(name.to_u64() & 0x1F_FFFF, 0x1F_FFFF);
+}
+
+#[test]
+fn identity_number_from_the_mac() {
+    let number = identity_number_from_mac([0x40, 0x4C, 0xCA, 0x5F, 0x12, 0x34]);
+    assert_eq!(number, 0x1F_1234);
+    assert_eq!(
+        Name::new().with_identity_number(number).identity_number,
+        number
+    );
+    // only 21 bits fit into the NAME
+    assert_eq!(
+        Name::new().with_identity_number(u32::MAX).identity_number,
+        0x1F_FFFF
+    );
+}
+
+#[test]
+fn claim_frame_is_sent_to_global() {
+    let mut claim = AddressClaim::new(Name::new(), DEFAULT_ADDRESS);
+    let frame = claim.start(at(0));
+
+    // priority 6, PGN 60928 to 0xFF, from 0xEE, data = NAME
+    assert_eq!(frame.id(), 0x18EEFFEE);
+    assert_eq!(frame.pgn(), 60928);
+    assert_eq!(frame.destination_address(), 0xFF);
+    assert_eq!(frame.data(), &Name::new().to_u64().to_le_bytes());
+}
+
+#[test]
+fn nothing_is_sent_before_the_claim_delay() {
+    let mut claim = AddressClaim::new(Name::new(), DEFAULT_ADDRESS);
+    assert!(!claim.may_send(at(1000)));
+    claim.start(at(1000));
+    assert!(!claim.may_send(at(1249)));
+    assert!(claim.may_send(at(1250)));
+}
+
+#[test]
+fn higher_priority_name_keeps_the_address() {
+    let mut claim = AddressClaim::new(name(10, true), DEFAULT_ADDRESS);
+    claim.start(at(0));
+
+    let reply = claim.on_address_claimed(DEFAULT_ADDRESS, name(20, true), at(500));
+    assert_eq!(reply.map(|f| f.source_address()), Some(DEFAULT_ADDRESS));
+    assert_eq!(claim.address(), Some(DEFAULT_ADDRESS));
+    assert!(claim.may_send(at(500)));
+}
+
+#[test]
+fn lower_priority_name_moves_to_a_free_address() {
+    let mut claim = AddressClaim::new(name(20, true), DEFAULT_ADDRESS);
+    claim.start(at(0));
+
+    // other ECUs already sit at 128 and 129
+    assert_eq!(claim.on_address_claimed(128, name(1, true), at(10)), None);
+    assert_eq!(claim.on_address_claimed(129, name(2, true), at(10)), None);
+
+    let reply = claim.on_address_claimed(DEFAULT_ADDRESS, name(10, true), at(500));
+    assert_eq!(reply.map(|f| f.source_address()), Some(130));
+    assert_eq!(claim.address(), Some(130));
+
+    // a new claim, so we wait again
+    assert!(!claim.may_send(at(700)));
+    assert!(claim.may_send(at(750)));
+}
+
+#[test]
+fn without_arbitrary_address_we_cannot_claim() {
+    let mut claim = AddressClaim::new(name(20, false), DEFAULT_ADDRESS);
+    claim.start(at(0));
+
+    let reply = claim.on_address_claimed(DEFAULT_ADDRESS, name(10, false), at(500));
+    assert_eq!(reply.map(|f| f.source_address()), Some(NULL_ADDRESS));
+    assert_eq!(claim.address(), None);
+    assert!(!claim.may_send(at(5000)));
+
+    // a request is answered with "cannot claim"
+    assert_eq!(claim.claim_frame().id(), 0x18EEFFFE);
+}
+
+#[test]
+fn claims_for_other_addresses_are_no_conflict() {
+    let mut claim = AddressClaim::new(Name::new(), DEFAULT_ADDRESS);
+    claim.start(at(0));
+    assert_eq!(claim.on_address_claimed(0x00, name(1, true), at(10)), None);
+    assert_eq!(claim.address(), Some(DEFAULT_ADDRESS));
+}
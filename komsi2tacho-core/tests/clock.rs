@@ -0,0 +1,54 @@
+// Host tests for the TimeDate frame before and after KOMSI sets the clock
+
+use komsi::KomsiDateTime;
+use komsi2tacho_core::can::current_date_time_frame;
+use komsi2tacho_core::config::{self, Config, TimeUnsetMode};
+use komsi2tacho_core::time::{clock_is_set, start_clock, sync_system_time};
+
+// the only test here, it changes the global clock and config
+#[test]
+fn time_date_waits_for_komsi() {
+    // nothing saved and no fallback: no time at all
+    start_clock(None);
+    assert!(current_date_time_frame().is_none());
+
+    config::set(Config {
+        fallback_date: 20260213,
+        ..Config::new()
+    });
+    start_clock(None);
+    assert!(!clock_is_set());
+    // by default nothing is sent
+    assert!(current_date_time_frame().is_none());
+
+    config::set(Config {
+        fallback_date: 20260213,
+        time_unset_mode: TimeUnsetMode::NotAvailable,
+        ..Config::new()
+    });
+    let frame = current_date_time_frame().expect("frame");
+    assert_eq!(frame.data(), &[0xFF; 8]);
+
+    config::set(Config {
+        fallback_date: 20260213,
+        time_unset_mode: TimeUnsetMode::Send,
+        ..Config::new()
+    });
+    let frame = current_date_time_frame().expect("frame");
+    // month and day (1/4 day per bit)
+    assert_eq!((frame.data()[3], frame.data()[4]), (2, 13 * 4));
+
+    // KOMSI sets the time, from now on it is sent in any mode
+    config::set(Config::new());
+    sync_system_time(KomsiDateTime {
+        year: 2025,
+        month: 11,
+        day: 5,
+        hour: 9,
+        min: 30,
+        sec: 0,
+    });
+    assert!(clock_is_set());
+    let frame = current_date_time_frame().expect("frame");
+    assert_eq!((frame.data()[3], frame.data()[4]), (11, 5 * 4));
+}
@@ -0,0 +1,225 @@
+// Host tests for the runtime configuration
+
+use komsi2tacho_core::config::{
+    self, CONFIG_KEYS, CommandInput, CommandLine, Config, ConfigStore, TimeUnsetMode, run_command,
+};
+use komsi2tacho_core::hal::{SECTOR_SIZE, Storage, StorageError};
+
+/// NOR flash in memory
+struct MemoryFlash {
+    bytes: Vec<u8>,
+    /// simulated power loss: only this many bytes of the next write reach the flash
+    cut_next_write: Option<usize>,
+}
+
+impl MemoryFlash {
+    fn new(sectors: u32) -> Self {
+        Self {
+            bytes: vec![0xFF; (sectors * SECTOR_SIZE) as usize],
+            cut_next_write: None,
+        }
+    }
+
+    fn range(&self, offset: u32, len: usize) -> Result<std::ops::Range<usize>, StorageError> {
+        let start = offset as usize;
+        if start + len > self.bytes.len() {
+            return Err(StorageError::OutOfBounds);
+        }
+        Ok(start..start + len)
+    }
+}
+
+impl Storage for &mut MemoryFlash {
+    fn capacity(&self) -> u32 {
+        self.bytes.len() as u32
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), StorageError> {
+        let range = self.range(offset, buf.len())?;
+        buf.copy_from_slice(&self.bytes[range]);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), StorageError> {
+        let range = self.range(offset, data.len())?;
+        let len = self.cut_next_write.take().unwrap_or(data.len());
+        for (old, new) in self.bytes[range].iter_mut().zip(data).take(len) {
+            *old &= new;
+        }
+        if len < data.len() {
+            return Err(StorageError::OutOfBounds);
+        }
+        Ok(())
+    }
+
+    fn erase_sector(&mut self, offset: u32) -> Result<(), StorageError> {
+        let range = self.range(offset, SECTOR_SIZE as usize)?;
+        self.bytes[range].fill(0xFF);
+        Ok(())
+    }
+}
+
+fn answer(command: &str) -> Vec<String> {
+    run_command(command).iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn key_ids_and_names_are_unique() {
+    for (i, key) in CONFIG_KEYS.iter().enumerate() {
+        for other in &CONFIG_KEYS[i + 1..] {
+            assert_ne!(key.id, other.id);
+            assert_ne!(key.name, other.name);
+        }
+        let default = (key.get)(&Config::new());
+        assert!(key.is_valid(default), "default of {} invalid", key.name);
+    }
+}
+
+// the only test that changes the global config
+#[test]
+fn serial_commands() {
+    assert_eq!(answer("get speed_clamp"), vec!["speed_clamp = 125"]);
+
+    assert_eq!(answer("set speed_clamp 100"), vec!["OK: speed_clamp = 100"]);
+    assert_eq!(config::get().speed_clamp, 100);
+
+    assert_eq!(
+        answer("set speed_clamp 300"),
+        vec!["ERR: speed_clamp must be 0..250"]
+    );
+    assert_eq!(
+        answer("set can_bitrate 300000"),
+        vec!["ERR: can_bitrate must be one of 125000 250000 500000 1000000"]
+    );
+    assert_eq!(
+        answer("set speed_clamp fast"),
+        vec!["ERR: not a number: fast"]
+    );
+    assert_eq!(answer("get top_speed"), vec!["ERR: unknown key top_speed"]);
+    assert_eq!(answer("speed"), vec!["ERR: use list, get, set or reset"]);
+    assert_eq!(config::get().speed_clamp, 100);
+
+    let list = answer("list");
+    assert_eq!(list.len(), CONFIG_KEYS.len());
+    assert!(list.contains(&"speed_clamp = 100 (0..250)".to_string()));
+
+    // keys with names are shown by name and take the name or the number
+    assert_eq!(
+        answer("get time_unset_mode"),
+        vec!["time_unset_mode = silent"]
+    );
+    assert_eq!(
+        answer("set time_unset_mode not_available"),
+        vec!["OK: time_unset_mode = not_available"]
+    );
+    assert_eq!(config::get().time_unset_mode, TimeUnsetMode::NotAvailable);
+    assert_eq!(
+        answer("set time_unset_mode 2"),
+        vec!["OK: time_unset_mode = send"]
+    );
+    assert_eq!(config::get().time_unset_mode, TimeUnsetMode::Send);
+    assert_eq!(
+        answer("set time_unset_mode later"),
+        vec!["ERR: time_unset_mode must be one of silent not_available send"]
+    );
+    assert!(
+        answer("list").contains(&"time_unset_mode = send (silent|not_available|send)".to_string())
+    );
+    assert_eq!(answer("set timezone_dst 1"), vec!["OK: timezone_dst = 1"]);
+
+    // k is not used once ratio and tyre are set
+    assert_eq!(
+        answer("set axle_ratio_milli 4100"),
+        vec!["OK: axle_ratio_milli = 4100"]
+    );
+    assert_eq!(
+        answer("set tyre_circumference_mm 3100"),
+        vec![
+            "OK: tyre_circumference_mm = 3100",
+            "SHAFT: ratio and tyre are set, k_factor is not used"
+        ]
+    );
+    assert_eq!(
+        answer("set k_factor 10580"),
+        vec![
+            "OK: k_factor = 10580",
+            "SHAFT: ratio and tyre are set, k_factor is not used"
+        ]
+    );
+    assert!(config::get().timezone_dst);
+
+    assert_eq!(answer("reset"), vec!["OK: config reset"]);
+    assert_eq!(config::get(), Config::new());
+}
+
+#[test]
+fn command_lines_are_split_from_komsi() {
+    let mut line = CommandLine::new();
+    let mut komsi = Vec::new();
+    let mut commands = Vec::new();
+    for &byte in b"y42\n:get speed_clamp\r\nA1\n" {
+        match line.push(byte) {
+            CommandInput::Komsi(b) => komsi.push(b),
+            CommandInput::Pending => {}
+            CommandInput::Complete(command) => commands.push(command.to_string()),
+        }
+    }
+    assert_eq!(komsi, b"y42\n\nA1\n");
+    assert_eq!(commands, vec!["get speed_clamp"]);
+}
+
+#[test]
+fn config_survives_reset() {
+    let mut flash = MemoryFlash::new(2);
+    let config = Config {
+        speed_clamp: 90,
+        k_factor: 6000,
+        ..Config::new()
+    };
+
+    let (mut store, saved) = ConfigStore::open(&mut flash).expect("open");
+    assert_eq!(saved, None);
+    store.save(&config).expect("save");
+
+    let (_, saved) = ConfigStore::open(&mut flash).expect("reopen");
+    assert_eq!(saved, Some(config));
+}
+
+#[test]
+fn newest_config_wins() {
+    let mut flash = MemoryFlash::new(2);
+    let (mut store, _) = ConfigStore::open(&mut flash).expect("open");
+    for clamp in [80, 90, 100, 110, 120] {
+        store
+            .save(&Config {
+                speed_clamp: clamp,
+                ..Config::new()
+            })
+            .expect("save");
+    }
+
+    let (_, saved) = ConfigStore::open(&mut flash).expect("reopen");
+    assert_eq!(saved.map(|c| c.speed_clamp), Some(120));
+}
+
+#[test]
+fn torn_write_keeps_the_old_config() {
+    let mut flash = MemoryFlash::new(2);
+    let old = Config {
+        speed_clamp: 80,
+        ..Config::new()
+    };
+    let (mut store, _) = ConfigStore::open(&mut flash).expect("open");
+    store.save(&old).expect("save");
+
+    flash.cut_next_write = Some(20);
+    let (mut store, _) = ConfigStore::open(&mut flash).expect("reopen");
+    let new = Config {
+        speed_clamp: 60,
+        ..Config::new()
+    };
+    assert!(store.save(&new).is_err());
+
+    let (_, saved) = ConfigStore::open(&mut flash).expect("reopen");
+    assert_eq!(saved, Some(old));
+}
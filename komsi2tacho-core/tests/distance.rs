@@ -0,0 +1,99 @@
+// Host tests for the distance integration
+
+use embassy_time::Instant;
+use komsi2tacho_core::distance::{DistanceIntegrator, UNITS_PER_METER, add_distance};
+use komsi2tacho_core::speed::Speed;
+use komsi2tacho_core::state::VehicleState;
+use komsi2tacho_core::trip::TRIP_COUNT;
+
+fn at_ms(ms: u64) -> Instant {
+    Instant::from_millis(ms)
+}
+
+/// drives with a constant speed in steps of `step_ms`, returns the state afterwards
+fn drive(speed: Speed, seconds: u64, step_ms: u64) -> VehicleState {
+    let mut state = VehicleState::new();
+    let mut integrator = DistanceIntegrator::new();
+    let mut now = 0;
+    integrator.advance(speed, at_ms(now));
+    while now < seconds * 1000 {
+        now += step_ms;
+        let units = integrator.advance(speed, at_ms(now));
+        add_distance(&mut state, units);
+    }
+    state
+}
+
+#[test]
+fn first_step_has_no_distance() {
+    let mut integrator = DistanceIntegrator::new();
+    assert_eq!(integrator.advance(Speed::from_kmh(50), at_ms(1000)), 0);
+}
+
+#[test]
+fn one_hour_at_36_kmh_is_36_km() {
+    let state = drive(Speed::from_kmh(36), 3600, 100);
+    assert_eq!(state.total_distance, 36_000);
+    assert_eq!(state.trips, [36_000; TRIP_COUNT]);
+    assert_eq!(state.distance_remainder, 0);
+}
+
+#[test]
+fn slow_speeds_are_not_lost() {
+    // 1 km/h is 0.28 m/s, the old integration counted 0 m forever
+    let state = drive(Speed::from_kmh(1), 3600, 100);
+    assert_eq!(state.total_distance, 1000);
+
+    // 3.5 km/h for 10 minutes
+    let state = drive(Speed::from_raw(896), 600, 100);
+    assert_eq!(state.total_distance, 583); // 583.33
+}
+
+#[test]
+fn step_size_does_not_matter() {
+    let speed = Speed::from_raw(12_345); // 48.22 km/h
+    let coarse = drive(speed, 600, 1000);
+    let fine = drive(speed, 600, 10);
+    let odd = drive(speed, 600, 7);
+    assert_eq!(coarse.total_distance, fine.total_distance);
+    assert_eq!(coarse.distance_remainder, fine.distance_remainder);
+    // 7 ms steps end 2 ms after the 10 minutes
+    assert!(odd.total_distance - fine.total_distance <= 1);
+}
+
+#[test]
+fn irregular_steps_use_real_time() {
+    // a late loop does not lose the time in between
+    let mut state = VehicleState::new();
+    let mut integrator = DistanceIntegrator::new();
+    let speed = Speed::from_kmh(72); // 20 m/s
+    integrator.advance(speed, at_ms(0));
+    for ms in [100, 350, 360, 1900, 2000] {
+        let units = integrator.advance(speed, at_ms(ms));
+        add_distance(&mut state, units);
+    }
+    assert_eq!(state.total_distance, 40);
+}
+
+#[test]
+fn speed_change_counts_from_then() {
+    let mut state = VehicleState::new();
+    let mut integrator = DistanceIntegrator::new();
+    integrator.advance(Speed::from_kmh(36), at_ms(0));
+    // 10 m/s for 1.5 s, then standing
+    let units = integrator.advance(Speed::ZERO, at_ms(1500));
+    add_distance(&mut state, units);
+    let units = integrator.advance(Speed::ZERO, at_ms(5000));
+    add_distance(&mut state, units);
+    assert_eq!(state.total_distance, 15);
+}
+
+#[test]
+fn remainder_carries_into_the_next_meter() {
+    let mut state = VehicleState::new();
+    add_distance(&mut state, UNITS_PER_METER / 2);
+    assert_eq!(state.total_distance, 0);
+    add_distance(&mut state, UNITS_PER_METER / 2);
+    assert_eq!(state.total_distance, 1);
+    assert_eq!(state.distance_remainder, 0);
+}
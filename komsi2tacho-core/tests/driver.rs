@@ -0,0 +1,117 @@
+// Host tests for the driver states in the tachograph frame
+
+use komsi2tacho_core::can::build_tachograph_frame;
+use komsi2tacho_core::commands::{komsi_dispatch, run_serial_command};
+use komsi2tacho_core::driver::working_state;
+use komsi2tacho_core::speed::Speed;
+use komsi2tacho_core::state::{self, TimeState, VehicleState, WorkingState};
+
+fn answer(command: &str) -> Vec<String> {
+    run_serial_command(command)
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+#[test]
+fn auto_follows_the_vehicle() {
+    let s = VehicleState::new();
+    assert!(s.driver1.auto);
+    assert_eq!(
+        working_state(&s.driver1, false, Speed::ZERO),
+        Some(WorkingState::Work)
+    );
+    assert_eq!(
+        working_state(&s.driver1, false, Speed::from_kmh(30)),
+        Some(WorkingState::Drive)
+    );
+
+    // driver 2 is not available, in auto the co-driver is available while moving
+    assert_eq!(working_state(&s.driver2, true, Speed::from_kmh(30)), None);
+    let mut codriver = s.driver2;
+    codriver.auto = true;
+    assert_eq!(
+        working_state(&codriver, true, Speed::from_kmh(30)),
+        Some(WorkingState::DriverAvailable)
+    );
+
+    // set explicitly it stays
+    let mut driver = s.driver1;
+    driver.auto = false;
+    driver.working_state = Some(WorkingState::RestSleeping);
+    assert_eq!(
+        working_state(&driver, false, Speed::from_kmh(30)),
+        Some(WorkingState::RestSleeping)
+    );
+}
+
+#[test]
+fn frame_carries_the_driver_bits() {
+    let mut s = VehicleState {
+        speed: Speed::from_kmh(50),
+        ..VehicleState::new()
+    };
+    let d = build_tachograph_frame(&s).data().to_vec();
+    // driver 1 drive (3), driver 2 not available (7)
+    assert_eq!(d[0] & 0x3F, 3 | 7 << 3);
+    // card 1 in, card 2 out, time states not available
+    assert_eq!(d[1] & 0x3F, 1 << 4 | 0x0F);
+    assert_eq!(d[2] & 0x3F, 0x0F);
+
+    s.driver1.time_state = Some(TimeState::Before4h30);
+    s.driver2.time_state = Some(TimeState::Reached16h);
+    s.driver2.card_present = true;
+    let d = build_tachograph_frame(&s).data().to_vec();
+    assert_eq!(d[1] & 0x3F, 1 << 4 | 1);
+    assert_eq!(d[2] & 0x3F, 1 << 4 | 6);
+}
+
+// the only test here that changes the global state
+#[test]
+fn serial_and_komsi_commands() {
+    assert_eq!(
+        answer("driver")[..2],
+        [
+            "driver 1: work (auto), card in, time none (auto)",
+            "driver 2: none, card out, time none"
+        ]
+    );
+
+    answer("driver 2 rest");
+    answer("driver 2 card in");
+    assert_eq!(
+        answer("driver 2 time 4h30")[..2],
+        [
+            "driver 1: work (auto), card in, time none (auto)",
+            "driver 2: rest, card in, time 4h30"
+        ]
+    );
+    assert!(answer("driver 3 rest")[0].starts_with("ERR"));
+    assert!(answer("driver 1 sleep")[0].starts_with("ERR"));
+
+    // the same from KOMSI
+    komsi_dispatch('V', b"1");
+    komsi_dispatch('R', b"0");
+    komsi_dispatch('S', b"3");
+    komsi_dispatch('T', b"15");
+    let s = state::snapshot();
+    assert_eq!(
+        (s.driver1.working_state, s.driver1.auto),
+        (Some(WorkingState::DriverAvailable), false)
+    );
+    assert!(!s.driver1.card_present && !s.driver2.card_present);
+    assert_eq!(s.driver1.time_state, Some(TimeState::Before9h));
+    assert!(!s.driver1.time_auto);
+    assert_eq!(s.driver2.time_state, None);
+
+    // unknown values change nothing, 9 is auto again
+    komsi_dispatch('V', b"5");
+    assert_eq!(
+        state::snapshot().driver1.working_state,
+        Some(WorkingState::DriverAvailable)
+    );
+    komsi_dispatch('V', b"9");
+    assert!(state::snapshot().driver1.auto);
+    answer("driver 1 time auto");
+    assert!(state::snapshot().driver1.time_auto);
+}
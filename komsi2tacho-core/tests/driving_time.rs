@@ -0,0 +1,155 @@
+// Host tests for the driving times after EU 561/2006, with a fake clock in seconds
+
+use komsi2tacho_core::driving_time::{DrivingTime, MAX_STEP};
+use komsi2tacho_core::state::TimeState;
+
+const MIN: i64 = 60;
+const HOUR: i64 = 60 * MIN;
+
+/// Drives or stands for a while in steps of one second, like the task
+struct Day {
+    times: DrivingTime,
+    clock: i64,
+}
+
+impl Day {
+    fn new() -> Self {
+        let mut times = DrivingTime::new();
+        times.step(0, false);
+        Self { times, clock: 0 }
+    }
+
+    fn run(&mut self, secs: i64, driving: bool) -> &mut Self {
+        // from now on we drive or stand
+        self.times.step(self.clock, driving);
+        for _ in 0..secs {
+            self.clock += 1;
+            self.times.step(self.clock, driving);
+        }
+        self
+    }
+
+    fn drive(&mut self, secs: i64) -> &mut Self {
+        self.run(secs, true)
+    }
+
+    fn stop(&mut self, secs: i64) -> &mut Self {
+        self.run(secs, false)
+    }
+
+    fn state(&self) -> TimeState {
+        self.times.time_state()
+    }
+}
+
+#[test]
+fn continuous_driving_warns_and_reaches_4h30() {
+    let mut day = Day::new();
+    assert_eq!(day.state(), TimeState::Normal);
+
+    day.drive(4 * HOUR + 14 * MIN + 59);
+    assert_eq!(day.state(), TimeState::Normal);
+    day.drive(1);
+    assert_eq!(day.state(), TimeState::Before4h30);
+    day.drive(15 * MIN - 1);
+    assert_eq!(day.state(), TimeState::Before4h30);
+    day.drive(1);
+    assert_eq!(day.state(), TimeState::Reached4h30);
+    assert_eq!(day.times.continuous, (4 * HOUR + 30 * MIN) as u64);
+
+    // a short stop is no break
+    day.stop(10 * MIN).drive(MIN);
+    assert_eq!(day.state(), TimeState::Reached4h30);
+
+    // 45 minutes are, already while standing
+    day.stop(45 * MIN);
+    assert_eq!(day.state(), TimeState::Normal);
+    assert_eq!(day.times.continuous, 0);
+}
+
+#[test]
+fn split_break_15_then_30() {
+    let mut day = Day::new();
+    day.drive(2 * HOUR)
+        .stop(15 * MIN)
+        .drive(2 * HOUR + 15 * MIN);
+    assert!(day.times.split_break);
+    assert_eq!(day.times.continuous, (4 * HOUR + 15 * MIN) as u64);
+    assert_eq!(day.state(), TimeState::Before4h30);
+
+    // the second part only needs 30 minutes
+    day.stop(30 * MIN);
+    assert_eq!(day.times.continuous, 0);
+    day.drive(MIN);
+    assert!(!day.times.split_break);
+
+    // the second part has to be at least 30 minutes
+    let mut day = Day::new();
+    day.drive(HOUR).stop(15 * MIN).drive(HOUR).stop(20 * MIN);
+    assert_eq!(day.times.continuous, (2 * HOUR) as u64);
+}
+
+#[test]
+fn daily_driving_9h() {
+    let mut day = Day::new();
+    // two blocks of 4 h with a full break
+    day.drive(4 * HOUR).stop(45 * MIN).drive(4 * HOUR);
+    assert_eq!(day.state(), TimeState::Normal);
+    day.stop(45 * MIN).drive(45 * MIN);
+    assert_eq!(day.state(), TimeState::Before9h);
+    day.drive(15 * MIN);
+    assert_eq!(day.state(), TimeState::Reached9h);
+    assert_eq!(day.times.daily, (9 * HOUR) as u64);
+
+    // a daily rest starts a new day
+    day.stop(9 * HOUR);
+    assert_eq!((day.times.daily, day.times.duty), (0, 0));
+    assert_eq!(day.state(), TimeState::Normal);
+}
+
+#[test]
+fn duty_period_16h() {
+    let mut day = Day::new();
+    // short trips with long stops, daily driving stays low
+    for _ in 0..5 {
+        day.drive(HOUR).stop(2 * HOUR);
+    }
+    assert_eq!(day.times.duty, (15 * HOUR) as u64);
+    assert_eq!(day.state(), TimeState::Normal);
+    day.stop(45 * MIN);
+    assert_eq!(day.state(), TimeState::Before16h);
+    day.stop(15 * MIN);
+    assert_eq!(day.state(), TimeState::Reached16h);
+
+    // a reached limit wins over a warning of another one
+    let mut day = Day::new();
+    day.drive(4 * HOUR + 20 * MIN);
+    assert_eq!(day.state(), TimeState::Before4h30);
+}
+
+#[test]
+fn clock_jumps() {
+    let mut times = DrivingTime::new();
+    times.step(1000, true);
+    times.step(1000 + 2 * MIN, true);
+    assert_eq!(times.continuous, 2 * MIN as u64);
+
+    // set back: no time
+    times.step(500, true);
+    assert_eq!(times.continuous, 2 * MIN as u64);
+
+    // set forward while driving: a rest, not driving time
+    times.step(500 + HOUR, true);
+    assert_eq!((times.continuous, times.daily), (0, 2 * MIN as u64));
+    times.step(501 + HOUR, true);
+    assert_eq!(times.continuous, 1);
+
+    // the longest step that still counts as driving
+    times.step(501 + HOUR + MAX_STEP as i64, true);
+    assert_eq!(times.continuous, 1 + MAX_STEP);
+
+    // set forward overnight while standing: a rest
+    times.step(600, false);
+    times.step(600 + 12 * HOUR, false);
+    assert_eq!((times.continuous, times.daily, times.duty), (0, 0, 0));
+}
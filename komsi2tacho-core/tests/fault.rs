@@ -0,0 +1,110 @@
+// Host tests for the injected tachograph events and faults
+
+use embassy_time::{Duration, Instant};
+use komsi2tacho_core::can::build_tachograph_frame;
+use komsi2tacho_core::commands::{komsi_dispatch, run_serial_command};
+use komsi2tacho_core::config::{self, Config};
+use komsi2tacho_core::fault::{Fault, FaultTimers};
+use komsi2tacho_core::state::{self, Faults, VehicleState};
+
+fn at(ms: u64) -> Instant {
+    Instant::from_millis(ms)
+}
+
+fn answer(command: &str) -> Vec<String> {
+    run_serial_command(command)
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+// byte 4: system event, handling information, tachograph performance (2 bits each)
+fn flags(faults: Faults) -> u8 {
+    let s = VehicleState {
+        faults,
+        ..VehicleState::new()
+    };
+    build_tachograph_frame(&s).data()[3] & 0x3F
+}
+
+#[test]
+fn faults_in_the_tachograph_frame() {
+    assert_eq!(flags(Faults::default()), 0);
+    let sensor = Faults {
+        sensor: true,
+        ..Faults::default()
+    };
+    assert_eq!(flags(sensor), 1 | 1 << 4);
+    let power = Faults {
+        power: true,
+        ..Faults::default()
+    };
+    assert_eq!(flags(power), 1);
+    let handling = Faults {
+        handling: true,
+        ..Faults::default()
+    };
+    assert_eq!(flags(handling), 1 << 2);
+    let performance = Faults {
+        performance: true,
+        ..Faults::default()
+    };
+    assert_eq!(flags(performance), 1 << 4);
+}
+
+#[test]
+fn timers_end_the_faults() {
+    let mut timers = FaultTimers::new();
+    timers.start(Fault::Sensor, at(0), Some(Duration::from_secs(60)));
+    timers.start(Fault::Handling, at(0), None);
+    timers.start(Fault::Power, at(10_000), Some(Duration::from_secs(5)));
+
+    assert_eq!(
+        timers.remaining(Fault::Sensor, at(15_000)),
+        Some(Duration::from_secs(45))
+    );
+    assert_eq!(timers.remaining(Fault::Handling, at(15_000)), None);
+    assert_eq!(timers.take_expired(at(14_999)), [false; 4]);
+    assert_eq!(timers.take_expired(at(15_000)), [false, true, false, false]);
+    assert_eq!(timers.take_expired(at(16_000)), [false; 4]);
+    assert_eq!(timers.take_expired(at(60_000)), [true, false, false, false]);
+
+    // stopped, it stays
+    timers.start(Fault::Performance, at(0), Some(Duration::from_secs(1)));
+    timers.stop(Fault::Performance);
+    assert_eq!(timers.take_expired(at(100_000)), [false; 4]);
+}
+
+// the only test here that changes the global state
+#[test]
+fn serial_and_komsi_commands() {
+    config::set(Config::new());
+    assert_eq!(
+        answer("fault"),
+        vec![
+            "fault sensor: off",
+            "fault power: off",
+            "fault handling: off",
+            "fault performance: off"
+        ]
+    );
+
+    let out = answer("fault sensor");
+    assert!(out[0].starts_with("fault sensor: on, "));
+    assert!(out[0].ends_with(" s left"));
+    assert_eq!(answer("fault handling 0")[2], "fault handling: on");
+    let s = state::snapshot();
+    assert!(s.faults.sensor && s.faults.handling && !s.faults.power);
+    assert_eq!(answer("fault sensor off")[0], "fault sensor: off");
+    assert!(answer("fault sensor x")[0].starts_with("ERR"));
+    assert!(answer("fault engine")[0].starts_with("ERR"));
+    answer("fault clear");
+    assert_eq!(state::snapshot().faults, Faults::default());
+
+    // the same from KOMSI
+    komsi_dispatch('Y', b"6");
+    let s = state::snapshot();
+    assert!(!s.faults.sensor && s.faults.power && s.faults.handling && !s.faults.performance);
+    komsi_dispatch('Y', b"0");
+    assert_eq!(state::snapshot().faults, Faults::default());
+}
@@ -0,0 +1,85 @@
+// Host tests for the direction of travel and the gear
+
+use komsi2tacho_core::can::{build_etc2_frame, build_tachograph_frame};
+use komsi2tacho_core::commands::{komsi_dispatch, run_serial_command};
+use komsi2tacho_core::gear::gear_code;
+use komsi2tacho_core::speed::Speed;
+use komsi2tacho_core::state::{self, Direction, VehicleState};
+
+fn answer(command: &str) -> Vec<String> {
+    run_serial_command(command)
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn with(direction: Direction, gear: u8) -> VehicleState {
+    VehicleState {
+        direction,
+        gear,
+        speed: Speed::from_kmh(5),
+        ..VehicleState::new()
+    }
+}
+
+#[test]
+fn direction_in_the_tachograph_frame() {
+    // bits 7-8 of byte 4, like before forward by default
+    let d = build_tachograph_frame(&VehicleState::new()).data().to_vec();
+    assert_eq!(d[3] >> 6, 1);
+    let d = build_tachograph_frame(&with(Direction::Reverse, 1))
+        .data()
+        .to_vec();
+    assert_eq!(d[3] >> 6, 0);
+    // the vehicle still moves
+    assert_eq!(d[0] >> 6, 1);
+    let d = build_tachograph_frame(&with(Direction::Neutral, 1))
+        .data()
+        .to_vec();
+    assert_eq!(d[3] >> 6, 1);
+}
+
+#[test]
+fn etc2_frame_carries_the_gear() {
+    let frame = build_etc2_frame(&VehicleState::new());
+    // priority 6, PGN 61445 (0xF005), from our address
+    assert_eq!(frame.id(), 0x18F005EE);
+    assert_eq!(frame.pgn(), 61445);
+    assert_eq!(frame.source_address(), 0xEE);
+    // selected and current gear with offset -125, the rest not available
+    assert_eq!(
+        frame.data(),
+        &[126, 0xFF, 0xFF, 126, 0xFF, 0xFF, 0xFF, 0xFF]
+    );
+
+    assert_eq!(gear_code(&with(Direction::Forward, 4)), 129);
+    assert_eq!(gear_code(&with(Direction::Neutral, 4)), 125);
+    assert_eq!(gear_code(&with(Direction::Reverse, 1)), 124);
+    assert_eq!(gear_code(&with(Direction::Reverse, 2)), 123);
+}
+
+// the only test here that changes the global state
+#[test]
+fn serial_and_komsi_commands() {
+    assert_eq!(answer("gear"), vec!["gear: forward 1"]);
+    assert_eq!(answer("gear reverse"), vec!["gear: reverse 1"]);
+    assert_eq!(answer("gear 2"), vec!["gear: reverse 2"]);
+    assert_eq!(answer("gear neutral"), vec!["gear: neutral"]);
+    assert!(answer("gear 0")[0].starts_with("ERR"));
+    assert!(answer("gear park")[0].starts_with("ERR"));
+    assert!(answer("gear 1 2")[0].starts_with("ERR"));
+
+    // the same from KOMSI
+    komsi_dispatch('W', b"1");
+    komsi_dispatch('Z', b"6");
+    let s = state::snapshot();
+    assert_eq!((s.direction, s.gear), (Direction::Forward, 6));
+    komsi_dispatch('W', b"2");
+    assert_eq!(state::snapshot().direction, Direction::Reverse);
+
+    // unknown values change nothing
+    komsi_dispatch('W', b"3");
+    komsi_dispatch('Z', b"0");
+    let s = state::snapshot();
+    assert_eq!((s.direction, s.gear), (Direction::Reverse, 6));
+}
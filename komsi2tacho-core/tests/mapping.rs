@@ -0,0 +1,112 @@
+// Host tests for the KOMSI command mapping table
+
+use komsi::KomsiCommand;
+use komsi2tacho_core::commands::{USB_TX_CHANNEL, komsi_dispatch};
+use komsi2tacho_core::mapping::{KOMSI_COMMANDS, Target, komsi_mapping, parse_value};
+
+fn usb_output() -> Vec<String> {
+    let mut out = Vec::new();
+    while let Ok(msg) = USB_TX_CHANNEL.try_receive() {
+        out.push(msg.as_str().to_string());
+    }
+    out
+}
+
+#[test]
+fn letters_are_unique() {
+    for (i, m) in KOMSI_COMMANDS.iter().enumerate() {
+        assert!(
+            KOMSI_COMMANDS[i + 1..].iter().all(|o| o.letter != m.letter),
+            "letter {} used twice",
+            m.letter
+        );
+    }
+}
+
+#[test]
+fn rows_match_the_komsi_letters() {
+    for m in KOMSI_COMMANDS {
+        if matches!(m.target, Target::Extension(_)) {
+            continue;
+        }
+        // the date needs all 14 digits, everything else takes a 1
+        let digits: &[u8] = if m.letter == 'r' {
+            b"20260101120000"
+        } else {
+            b"1"
+        };
+        // e.g. "Ok(MaxSpeed(1))" for "max speed"
+        let debug = format!("{:?}", KomsiCommand::from_parts(m.letter, digits));
+        let variant = debug
+            .strip_prefix("Ok(")
+            .and_then(|d| d.split('(').next())
+            .map(|d| d.to_lowercase());
+        assert_eq!(
+            variant,
+            Some(m.name.replace(' ', "")),
+            "letter {} is {}",
+            m.letter,
+            debug
+        );
+    }
+}
+
+#[test]
+fn extension_letters_are_free_in_komsi() {
+    for m in KOMSI_COMMANDS {
+        if matches!(m.target, Target::Extension(_)) {
+            assert!(
+                KomsiCommand::from_parts(m.letter, b"1").is_err(),
+                "letter {} ({}) is a KOMSI command",
+                m.letter,
+                m.name
+            );
+        }
+    }
+}
+
+#[test]
+fn state_rows_are_sent() {
+    // a supported value must reach the tacho, otherwise show_info lists it for nothing
+    for m in KOMSI_COMMANDS {
+        if matches!(m.target, Target::State(_) | Target::Extension(_)) {
+            assert!(
+                m.pgn.is_some(),
+                "letter {} ({}) has no PGN",
+                m.letter,
+                m.name
+            );
+        }
+    }
+    assert!(komsi_mapping('G').is_some_and(|m| !m.is_supported()));
+}
+
+#[test]
+fn values_are_whole_numbers() {
+    assert_eq!(parse_value(b"15"), Some(15));
+    assert_eq!(parse_value(b"0"), Some(0));
+    assert_eq!(parse_value(b"99999999999"), Some(u32::MAX));
+    // the '.' of a decimal speed, and before the digits too
+    assert_eq!(parse_value(b"1.5"), None);
+    assert_eq!(parse_value(b".2"), None);
+}
+
+#[test]
+fn unsupported_commands_are_answered_once() {
+    let unsupported = KOMSI_COMMANDS
+        .iter()
+        .find(|m| !m.is_supported())
+        .map(|m| m.letter)
+        .unwrap_or('B');
+
+    komsi_dispatch(unsupported, b"1");
+    komsi_dispatch(unsupported, b"0");
+    let out = usb_output();
+    assert_eq!(out.len(), 1);
+    assert!(out[0].starts_with(&format!("ERR: KOMSI {unsupported} (")));
+    assert!(out[0].ends_with("not supported"));
+
+    // letters the protocol does not know at all
+    komsi_dispatch('X', b"5");
+    assert_eq!(usb_output(), vec!["ERR: KOMSI X unknown".to_string()]);
+}
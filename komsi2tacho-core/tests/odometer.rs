@@ -0,0 +1,227 @@
+// Host tests for the persistent odometer, with a file as flash
+
+use komsi2tacho_core::hal::{SECTOR_SIZE, Storage, StorageError};
+use komsi2tacho_core::odometer::{OdometerRecord, OdometerStore, RECORD_SIZE, crc32};
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+
+/// NOR flash in a file: erased = 0xFF, writing can only clear bits
+struct FileFlash {
+    file: File,
+    capacity: u32,
+    /// simulated power loss: only this many bytes of the next write reach the flash
+    cut_next_write: Option<usize>,
+}
+
+impl FileFlash {
+    fn create(name: &str, sectors: u32) -> Self {
+        let path = temp_path(name);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .expect("cannot create flash file");
+        let capacity = sectors * SECTOR_SIZE;
+        file.write_all_at(&vec![0xFF; capacity as usize], 0)
+            .expect("cannot erase flash file");
+        Self::with_file(file, capacity)
+    }
+
+    /// the same file again, like after a reset
+    fn reopen(name: &str, sectors: u32) -> Self {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(temp_path(name))
+            .expect("cannot open flash file");
+        Self::with_file(file, sectors * SECTOR_SIZE)
+    }
+
+    fn with_file(file: File, capacity: u32) -> Self {
+        Self {
+            file,
+            capacity,
+            cut_next_write: None,
+        }
+    }
+
+    fn check(&self, offset: u32, len: usize) -> Result<(), StorageError> {
+        if offset as usize + len > self.capacity as usize {
+            return Err(StorageError::OutOfBounds);
+        }
+        Ok(())
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("komsi2tacho-{}-{name}.bin", std::process::id()))
+}
+
+fn io_error(_: std::io::Error) -> StorageError {
+    StorageError::Other(heapless::String::try_from("io error").unwrap_or_default())
+}
+
+impl Storage for FileFlash {
+    fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), StorageError> {
+        self.check(offset, buf.len())?;
+        self.file
+            .read_exact_at(buf, offset as u64)
+            .map_err(io_error)
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), StorageError> {
+        self.check(offset, data.len())?;
+        let mut current = vec![0u8; data.len()];
+        self.file
+            .read_exact_at(&mut current, offset as u64)
+            .map_err(io_error)?;
+        let len = self.cut_next_write.take().unwrap_or(data.len());
+        let written: Vec<u8> = current
+            .iter()
+            .zip(data)
+            .take(len)
+            .map(|(old, new)| old & new)
+            .collect();
+        self.file
+            .write_all_at(&written, offset as u64)
+            .map_err(io_error)?;
+        if len < data.len() {
+            return Err(StorageError::OutOfBounds);
+        }
+        Ok(())
+    }
+
+    fn erase_sector(&mut self, offset: u32) -> Result<(), StorageError> {
+        self.check(offset, SECTOR_SIZE as usize)?;
+        self.file
+            .write_all_at(&[0xFF; SECTOR_SIZE as usize], offset as u64)
+            .map_err(io_error)
+    }
+}
+
+#[test]
+fn record_round_trip() {
+    let record = OdometerRecord {
+        sequence: 7,
+        total_distance: 123_456_789,
+        trips: [4711, 815, 0],
+        time: Some(1_781_337_600),
+    };
+    assert_eq!(OdometerRecord::from_bytes(&record.to_bytes()), Some(record));
+
+    // erased flash and flipped bits are no record
+    assert_eq!(
+        OdometerRecord::from_bytes(&[0xFF; RECORD_SIZE as usize]),
+        None
+    );
+    let mut bytes = record.to_bytes();
+    bytes[9] ^= 0x01;
+    assert_eq!(OdometerRecord::from_bytes(&bytes), None);
+}
+
+#[test]
+fn crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn empty_flash_has_no_record() {
+    let store = OdometerStore::open(FileFlash::create("empty", 2));
+    assert!(store.is_ok_and(|s| s.last().is_none()));
+}
+
+#[test]
+fn one_sector_is_not_enough() {
+    assert!(OdometerStore::open(FileFlash::create("small", 1)).is_err());
+}
+
+#[test]
+fn newest_record_survives_reset() {
+    let mut store = OdometerStore::open(FileFlash::create("reset", 2)).expect("open");
+    for km in 1..=10u64 {
+        store
+            .save(km * 1000, [km * 10, km, 0], Some(km as i64 * 60))
+            .expect("save");
+    }
+    drop(store);
+
+    let store = OdometerStore::open(FileFlash::reopen("reset", 2)).expect("reopen");
+    let last = store.last().expect("record");
+    assert_eq!((last.total_distance, last.trips), (10_000, [100, 10, 0]));
+    assert_eq!(last.time, Some(600));
+}
+
+#[test]
+fn wraps_around_and_levels_wear() {
+    let sectors = 3;
+    let records_per_sector = SECTOR_SIZE / RECORD_SIZE;
+    let mut store = OdometerStore::open(FileFlash::create("wear", sectors)).expect("open");
+
+    // three times through the whole flash
+    let saves = 3 * sectors * records_per_sector;
+    for i in 0..saves as u64 {
+        store.save(i, [0; 3], None).expect("save");
+    }
+    drop(store);
+
+    let mut store = OdometerStore::open(FileFlash::reopen("wear", sectors)).expect("reopen");
+    assert_eq!(
+        store.last().map(|r| r.total_distance),
+        Some(saves as u64 - 1)
+    );
+
+    // and it goes on from there
+    store.save(99_999, [0; 3], None).expect("save");
+    let store = OdometerStore::open(FileFlash::reopen("wear", sectors)).expect("reopen");
+    assert_eq!(store.last().map(|r| r.total_distance), Some(99_999));
+}
+
+#[test]
+fn all_sectors_are_used() {
+    let sectors = 4;
+    let records_per_sector = SECTOR_SIZE / RECORD_SIZE;
+    let mut store = OdometerStore::open(FileFlash::create("sectors", sectors)).expect("open");
+    for i in 0..(2 * sectors * records_per_sector) as u64 {
+        store.save(i, [0; 3], None).expect("save");
+    }
+    drop(store);
+
+    // every sector was erased once more and holds records of the second round
+    let mut flash = FileFlash::reopen("sectors", sectors);
+    let mut bytes = [0u8; RECORD_SIZE as usize];
+    for sector in 0..sectors {
+        flash.read(sector * SECTOR_SIZE, &mut bytes).expect("read");
+        let record = OdometerRecord::from_bytes(&bytes).expect("record");
+        assert!(record.sequence >= sectors * records_per_sector);
+    }
+}
+
+#[test]
+fn torn_write_keeps_the_record_before() {
+    let mut store = OdometerStore::open(FileFlash::create("torn", 2)).expect("open");
+    store.save(1000, [1; 3], None).expect("save");
+    store.save(2000, [2; 3], None).expect("save");
+    drop(store);
+
+    // power loss in the middle of the third record
+    let mut flash = FileFlash::reopen("torn", 2);
+    flash.cut_next_write = Some(12);
+    let mut store = OdometerStore::open(flash).expect("reopen");
+    assert!(store.save(3000, [3; 3], None).is_err());
+    drop(store);
+
+    let mut store = OdometerStore::open(FileFlash::reopen("torn", 2)).expect("reopen");
+    assert_eq!(store.last().map(|r| r.total_distance), Some(2000));
+
+    // the torn slot is skipped, the next save works
+    store.save(4000, [4; 3], None).expect("save");
+    let store = OdometerStore::open(FileFlash::reopen("torn", 2)).expect("reopen");
+    assert_eq!(store.last().map(|r| r.total_distance), Some(4000));
+}
@@ -0,0 +1,153 @@
+// Host tests for the overspeed flag and its event log
+
+use embassy_time::Instant;
+use komsi2tacho_core::can::build_tachograph_frame;
+use komsi2tacho_core::commands::{komsi_dispatch, run_serial_command};
+use komsi2tacho_core::config::{self, Config};
+use komsi2tacho_core::overspeed::{self, OverspeedDetector, OverspeedSettings};
+use komsi2tacho_core::speed::Speed;
+use komsi2tacho_core::state;
+
+fn at(ms: u64) -> Instant {
+    Instant::from_millis(ms)
+}
+
+fn kmh(kmh: u32) -> Speed {
+    Speed::from_kmh(kmh)
+}
+
+fn settings(tolerance_kmh: u32, min_duration_ms: u64, hysteresis_kmh: u32) -> OverspeedSettings {
+    OverspeedSettings {
+        tolerance_kmh,
+        min_duration_ms,
+        hysteresis_kmh,
+    }
+}
+
+fn answer(command: &str) -> Vec<String> {
+    run_serial_command(command)
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+#[test]
+fn defaults_set_the_flag_at_once() {
+    let s = OverspeedSettings::from_config(&Config::new());
+    assert_eq!(s, settings(0, 0, 2));
+
+    let mut detector = OverspeedDetector::new();
+    assert_eq!(detector.step(kmh(80), 80, at(0), None, &s), None);
+    assert!(!detector.is_active());
+    detector.step(Speed::from_raw(80 * 256 + 1), 80, at(45), None, &s);
+    assert!(detector.is_active());
+
+    // no limit, no overspeed
+    let mut detector = OverspeedDetector::new();
+    detector.step(kmh(120), 0, at(0), None, &s);
+    assert!(!detector.is_active());
+}
+
+#[test]
+fn hysteresis_stops_the_flickering() {
+    let s = settings(0, 0, 2);
+    let mut detector = OverspeedDetector::new();
+    detector.step(kmh(81), 80, at(0), Some(1000), &s);
+    assert!(detector.is_active());
+
+    // around the limit the flag stays
+    for (i, speed) in [80, 79, 81, 79, 80].iter().enumerate() {
+        assert_eq!(
+            detector.step(kmh(*speed), 80, at(45 * i as u64 + 45), Some(1000), &s),
+            None
+        );
+        assert!(detector.is_active());
+    }
+
+    let event = detector
+        .step(kmh(78), 80, at(3000), Some(1003), &s)
+        .expect("overspeed is over");
+    assert!(!detector.is_active());
+    assert_eq!(event.start, Some(1000));
+    assert_eq!(event.duration_ms, 3000);
+    assert_eq!(event.max_speed, kmh(81));
+    assert_eq!(event.limit_kmh, 80);
+}
+
+#[test]
+fn tolerance_and_minimum_duration() {
+    let s = settings(5, 60_000, 2);
+    let mut detector = OverspeedDetector::new();
+
+    // within the tolerance
+    detector.step(kmh(85), 80, at(0), None, &s);
+    assert_eq!(detector.current(), None);
+
+    // above, but not long enough: no flag and no event
+    detector.step(kmh(90), 80, at(1000), None, &s);
+    assert!(!detector.is_active());
+    assert_eq!(detector.step(kmh(85), 80, at(59_000), None, &s), None);
+    assert!(!detector.is_active());
+
+    // 60 s above
+    detector.step(kmh(86), 80, at(100_000), None, &s);
+    detector.step(kmh(95), 80, at(159_999), None, &s);
+    assert!(!detector.is_active());
+    detector.step(kmh(90), 80, at(160_000), None, &s);
+    assert!(detector.is_active());
+    let current = detector.current().expect("overspeed goes on");
+    assert_eq!(current.duration_ms, 60_000);
+    assert_eq!(current.max_speed, kmh(95));
+
+    // released below 80 + 5 - 2 km/h
+    assert_eq!(detector.step(kmh(84), 80, at(170_000), None, &s), None);
+    let event = detector
+        .step(kmh(83), 80, at(180_000), None, &s)
+        .expect("overspeed is over");
+    assert_eq!(event.duration_ms, 80_000);
+
+    // the limit is lifted
+    let mut detector = OverspeedDetector::new();
+    detector.step(kmh(100), 80, at(0), None, &s);
+    detector.step(kmh(100), 80, at(60_000), None, &s);
+    assert!(detector.is_active());
+    assert!(detector.step(kmh(100), 0, at(61_000), None, &s).is_some());
+
+    // a huge tolerance does not overflow, the limit is just never reached
+    let huge = settings(u32::MAX, 60_000, 2);
+    let mut detector = OverspeedDetector::new();
+    detector.step(kmh(100), 80, at(0), None, &huge);
+    assert_eq!(detector.step(kmh(100), 80, at(60_000), None, &huge), None);
+    assert!(!detector.is_active());
+}
+
+// the only test here that changes the global state
+#[test]
+fn flag_in_the_frame_and_event_log() {
+    config::set(Config::new());
+    komsi_dispatch('s', b"60");
+    komsi_dispatch('y', b"70");
+    let s = state::snapshot();
+    assert!(s.overspeed);
+    assert_eq!(build_tachograph_frame(&s).data()[1] >> 6, 0b01);
+    assert_eq!(answer("overspeed").len(), 2);
+
+    komsi_dispatch('y', b"59");
+    assert!(state::snapshot().overspeed);
+    komsi_dispatch('y', b"50");
+    let s = state::snapshot();
+    assert!(!s.overspeed);
+    assert_eq!(build_tachograph_frame(&s).data()[1] >> 6, 0b00);
+
+    assert_eq!(overspeed::events().len(), 1);
+    let log = answer("overspeed");
+    assert_eq!(log[0], "overspeed events: 1");
+    assert!(log[1].starts_with("1: no time, 0 s, max 70 km/h, limit 60"));
+
+    assert_eq!(
+        answer("overspeed clear"),
+        vec!["OK: overspeed events cleared"]
+    );
+    assert!(overspeed::events().is_empty());
+    assert!(answer("overspeed foo")[0].starts_with("ERR"));
+}
@@ -0,0 +1,139 @@
+// Host tests for the way KOMSI bytes -> vehicle state -> J1939 frames
+//
+// run with: cargo test -p komsi2tacho-core --target <host-target>
+// (the workspace default target is the ESP32-C6)
+
+use komsi2tacho_core::can::{build_eec1_frame, build_hr_distance_frame, build_tachograph_frame};
+use komsi2tacho_core::commands::{KomsiParser, komsi_dispatch};
+use komsi2tacho_core::speed::Speed;
+use komsi2tacho_core::state::{self, VehicleState};
+
+fn feed(parser: &mut KomsiParser, input: &[u8]) {
+    for &byte in input {
+        if let Some(cmd) = parser.push(byte) {
+            komsi_dispatch(cmd.letter, &cmd.digits);
+        }
+    }
+}
+
+#[test]
+fn parser_splits_commands() {
+    let mut parser = KomsiParser::new();
+    let mut commands = Vec::new();
+    for &byte in b"A1y42;z50\n" {
+        if let Some(cmd) = parser.push(byte) {
+            commands.push((cmd.letter, cmd.digits.to_vec()));
+        }
+    }
+
+    assert_eq!(
+        commands,
+        vec![
+            ('A', b"1".to_vec()),
+            ('y', b"42".to_vec()),
+            ('z', b"50".to_vec())
+        ]
+    );
+}
+
+#[test]
+fn parser_ignores_digits_without_command() {
+    let mut parser = KomsiParser::new();
+    assert_eq!(parser.push(b'1'), None);
+    assert_eq!(parser.push(b'\n'), None);
+}
+
+#[test]
+fn tachograph_frame_carries_speed() {
+    let state = VehicleState {
+        speed: Speed::from_kmh(50),
+        ..VehicleState::new()
+    };
+    let frame = build_tachograph_frame(&state);
+
+    // priority 3, PGN 65132 (0xFE6C), source address 0xEE
+    assert_eq!(frame.id(), 0x0CFE6CEE);
+    assert_eq!(frame.pgn(), 65132);
+    assert_eq!(frame.data().len(), 8);
+
+    // vehicle speed 1/256 km/h per bit
+    let speed = u16::from_le_bytes([frame.data()[6], frame.data()[7]]);
+    assert_eq!(speed, 50 * 256);
+
+    // vehicle motion (bits 7-8 of byte 1) is set
+    assert_eq!(frame.data()[0] >> 6, 0b01);
+}
+
+#[test]
+fn hr_distance_frame_ids() {
+    let frame = build_hr_distance_frame(&VehicleState::new());
+    assert_eq!(frame.id(), 0x18FEC1EE);
+}
+
+#[test]
+fn eec1_frame_carries_engine_speed() {
+    let state = VehicleState {
+        engine_rpm: 1500,
+        ..VehicleState::new()
+    };
+    let frame = build_eec1_frame(&state);
+
+    // priority 3, PGN 61444 (0xF004), from our address, which also controls the engine
+    assert_eq!(frame.id(), 0x0CF004EE);
+    assert_eq!(frame.pgn(), 61444);
+    assert_eq!(frame.source_address(), 0xEE);
+    assert_eq!(frame.data()[5], 0xEE);
+
+    // engine speed 0.125 rpm per bit in bytes 4-5
+    let rpm = u16::from_le_bytes([frame.data()[3], frame.data()[4]]);
+    assert_eq!(rpm, 1500 * 8);
+
+    // more than fits in 16 bits is clamped
+    let state = VehicleState {
+        engine_rpm: 20000,
+        ..VehicleState::new()
+    };
+    let frame = build_eec1_frame(&state);
+    assert_eq!(
+        u16::from_le_bytes([frame.data()[3], frame.data()[4]]),
+        8031 * 8
+    );
+}
+
+#[test]
+fn komsi_bytes_to_tachograph_frame() {
+    let mut parser = KomsiParser::new();
+    feed(&mut parser, b"y80\ns60\n");
+
+    let snapshot = state::snapshot();
+    assert_eq!(snapshot.speed, Speed::from_kmh(80));
+    assert_eq!(snapshot.max_speed, 60);
+
+    // overspeed (bits 7-8 of byte 2) is set
+    let frame = build_tachograph_frame(&snapshot);
+    assert_eq!(frame.data()[1] >> 6, 0b01);
+
+    // more than 125 km/h is clamped to protect the needle
+    feed(&mut parser, b"y200\n");
+    assert_eq!(state::snapshot().speed, Speed::from_kmh(125));
+}
+
+#[test]
+fn ignition_off_stops_the_vehicle() {
+    let mut parser = KomsiParser::new();
+    feed(&mut parser, b"A1\ny30\nt1500\n");
+    assert_eq!(state::snapshot().speed, Speed::from_kmh(30));
+    assert_eq!(state::snapshot().engine_rpm, 1500);
+
+    feed(&mut parser, b"A0\n");
+    let snapshot = state::snapshot();
+    assert!(!snapshot.ignition);
+    assert_eq!(snapshot.speed, Speed::ZERO);
+
+    // speed and rpm without ignition are ignored, vehicle motion stays cleared
+    feed(&mut parser, b"y50\nt800\n");
+    assert_eq!(state::snapshot().engine_rpm, 0);
+    let frame = build_tachograph_frame(&state::snapshot());
+    assert_eq!(frame.data()[0] >> 6, 0b00);
+    feed(&mut parser, b"A1\n");
+}
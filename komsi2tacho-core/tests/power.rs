@@ -0,0 +1,48 @@
+// Host tests for the ignition / power state machine
+
+use embassy_time::{Duration, Instant};
+use komsi2tacho_core::power::PowerState;
+
+const RUN_ON: Duration = Duration::from_secs(3);
+
+fn at(ms: u64) -> Instant {
+    Instant::from_millis(ms)
+}
+
+#[test]
+fn ignition_on_starts_broadcasts_in_order() {
+    let state = PowerState::Off.next(true, at(0), RUN_ON);
+    assert_eq!(state, PowerState::Starting { since: at(0) });
+
+    // TimeDate first, then distance, engine and tachograph
+    assert_eq!(state.broadcasts(at(0)), 0x01);
+    assert_eq!(state.broadcasts(at(120)), 0x03);
+    assert_eq!(state.broadcasts(at(150)), 0x0B);
+    assert_eq!(state.broadcasts(at(200)), 0x0F);
+
+    assert_eq!(state.next(true, at(100), RUN_ON), state);
+    assert_eq!(state.next(true, at(200), RUN_ON), PowerState::Running);
+}
+
+#[test]
+fn ignition_off_keeps_sending_for_run_on_time() {
+    let state = PowerState::Running.next(false, at(1000), RUN_ON);
+    assert_eq!(state, PowerState::RunOn { since: at(1000) });
+    assert_eq!(state.broadcasts(at(2000)), 0x0F);
+
+    assert_eq!(state.next(false, at(3999), RUN_ON), state);
+    let state = state.next(false, at(4000), RUN_ON);
+    assert_eq!(state, PowerState::Off);
+    assert_eq!(state.broadcasts(at(4000)), 0);
+}
+
+#[test]
+fn ignition_on_during_run_on_continues() {
+    let state = PowerState::RunOn { since: at(0) };
+    assert_eq!(state.next(true, at(500), RUN_ON), PowerState::Running);
+}
+
+#[test]
+fn off_stays_off() {
+    assert_eq!(PowerState::Off.next(false, at(0), RUN_ON), PowerState::Off);
+}
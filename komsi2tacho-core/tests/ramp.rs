@@ -0,0 +1,90 @@
+// Host tests for the speed ramp of the tachograph needle
+
+use embassy_time::Instant;
+use komsi2tacho_core::config::Config;
+use komsi2tacho_core::ramp::{RampSettings, SpeedRamp};
+use komsi2tacho_core::speed::Speed;
+
+fn at(ms: u64) -> Instant {
+    Instant::from_millis(ms)
+}
+
+fn kmh(kmh: u32) -> Speed {
+    Speed::from_kmh(kmh)
+}
+
+fn settings(max_acceleration: u32, latency_ms: u32) -> RampSettings {
+    RampSettings {
+        max_acceleration,
+        latency_ms,
+    }
+}
+
+#[test]
+fn off_by_default() {
+    let off = RampSettings::from_config(&Config::new());
+    assert!(!off.is_enabled());
+
+    let mut ramp = SpeedRamp::new();
+    assert_eq!(ramp.step(kmh(50), at(0), &off), kmh(50));
+    assert_eq!(ramp.step(kmh(20), at(10), &off), kmh(20));
+    assert!(ramp.is_settled());
+}
+
+#[test]
+fn new_value_is_reached_within_latency() {
+    let s = settings(0, 200);
+    let mut ramp = SpeedRamp::new();
+    assert_eq!(ramp.step(kmh(0), at(0), &s), kmh(0));
+    assert_eq!(ramp.step(kmh(50), at(0), &s), kmh(0));
+    // 11.25 km/h, the ramp keeps the fraction
+    assert_eq!(ramp.step(kmh(50), at(45), &s), Speed::from_raw(2880));
+    assert_eq!(ramp.step(kmh(50), at(100), &s), kmh(25));
+    assert!(!ramp.is_settled());
+    assert_eq!(ramp.step(kmh(50), at(200), &s), kmh(50));
+    assert!(ramp.is_settled());
+    assert_eq!(ramp.step(kmh(50), at(300), &s), kmh(50));
+}
+
+#[test]
+fn acceleration_is_limited() {
+    let s = settings(10, 100);
+    let mut ramp = SpeedRamp::new();
+    ramp.step(kmh(0), at(0), &s);
+    ramp.step(kmh(50), at(0), &s);
+    assert_eq!(ramp.step(kmh(50), at(1000), &s), kmh(10));
+    assert_eq!(ramp.step(kmh(50), at(2500), &s), kmh(25));
+    assert_eq!(ramp.step(kmh(50), at(5000), &s), kmh(50));
+
+    // braking is limited the same way
+    assert_eq!(ramp.step(kmh(0), at(5000), &s), kmh(50));
+    assert_eq!(ramp.step(kmh(0), at(6000), &s), kmh(40));
+    assert_eq!(ramp.step(kmh(0), at(10_000), &s), kmh(0));
+}
+
+#[test]
+fn new_sample_while_moving() {
+    let s = settings(0, 200);
+    let mut ramp = SpeedRamp::new();
+    ramp.step(kmh(0), at(0), &s);
+    ramp.step(kmh(50), at(0), &s);
+    assert_eq!(ramp.step(kmh(50), at(100), &s), kmh(25));
+
+    // from 25 to 35 in the next 200 ms
+    assert_eq!(ramp.step(kmh(35), at(100), &s), kmh(25));
+    assert_eq!(ramp.step(kmh(35), at(200), &s), kmh(30));
+    assert_eq!(ramp.step(kmh(35), at(300), &s), kmh(35));
+
+    // and back down below the current value
+    assert_eq!(ramp.step(kmh(15), at(300), &s), kmh(35));
+    assert_eq!(ramp.step(kmh(15), at(400), &s), kmh(25));
+    assert_eq!(ramp.step(kmh(15), at(500), &s), kmh(15));
+}
+
+#[test]
+fn switching_off_jumps_to_the_komsi_value() {
+    let mut ramp = SpeedRamp::new();
+    ramp.step(kmh(80), at(0), &settings(5, 0));
+    assert_eq!(ramp.step(kmh(80), at(1000), &settings(5, 0)), kmh(5));
+    assert_eq!(ramp.step(kmh(80), at(1001), &settings(0, 0)), kmh(80));
+}
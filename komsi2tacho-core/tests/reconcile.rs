@@ -0,0 +1,182 @@
+// Host tests for the odometer reconciliation
+
+use komsi2tacho_core::commands::run_serial_command;
+use komsi2tacho_core::config::Config;
+use komsi2tacho_core::reconcile::{
+    ReconcileSettings, Reconciled, Slew, force, reconcile, simulator_odometer,
+};
+use komsi2tacho_core::state::{self, VehicleState};
+use komsi2tacho_core::trip::TRIP_COUNT;
+
+fn settings() -> ReconcileSettings {
+    ReconcileSettings::from_config(&Config::new())
+}
+
+fn driven(total: u64, trip: u64) -> VehicleState {
+    VehicleState {
+        total_distance: total,
+        trips: [trip; TRIP_COUNT],
+        ignition: true,
+        ..VehicleState::new()
+    }
+}
+
+#[test]
+fn defaults() {
+    assert_eq!(
+        settings(),
+        ReconcileSettings {
+            enabled: true,
+            slew_rate: 5,
+            jump_limit: 500,
+        }
+    );
+}
+
+#[test]
+fn small_drift_is_slewed() {
+    let mut s = driven(10_000, 700);
+    assert_eq!(
+        reconcile(&mut s, 10_040, &settings()),
+        Reconciled::Slewing(40)
+    );
+    // nothing jumps, the trip stays
+    assert_eq!((s.total_distance, s.trips[0]), (10_000, 700));
+    assert_eq!(s.odometer_correction, 40);
+
+    assert_eq!(
+        reconcile(&mut s, 9_990, &settings()),
+        Reconciled::Slewing(-10)
+    );
+    assert_eq!(s.odometer_correction, -10);
+
+    assert_eq!(reconcile(&mut s, 10_000, &settings()), Reconciled::InSync);
+    assert_eq!(s.odometer_correction, 0);
+}
+
+#[test]
+fn big_step_forward_is_taken() {
+    let mut s = driven(0, 0);
+    assert_eq!(
+        reconcile(&mut s, 123_456_000, &settings()),
+        Reconciled::Jumped(123_456_000)
+    );
+    assert_eq!(s.total_distance, 123_456_000);
+}
+
+#[test]
+fn big_step_backwards_needs_force() {
+    let mut s = driven(50_000, 1200);
+    assert_eq!(
+        reconcile(&mut s, 20_000, &settings()),
+        Reconciled::Rejected(-30_000)
+    );
+    assert_eq!(s.total_distance, 50_000);
+
+    force(&mut s, 20_000);
+    assert_eq!((s.total_distance, s.trips[0]), (20_000, 1200));
+}
+
+#[test]
+fn without_reconciliation_the_value_is_set() {
+    let off = ReconcileSettings {
+        enabled: false,
+        ..settings()
+    };
+    let mut s = driven(50_000, 1200);
+    assert_eq!(reconcile(&mut s, 20_000, &off), Reconciled::Set);
+    assert_eq!((s.total_distance, s.trips), (20_000, [0; TRIP_COUNT]));
+}
+
+#[test]
+fn slew_catches_up_with_the_rate() {
+    let mut s = driven(10_000, 0);
+    s.odometer_correction = 12;
+    let mut slew = Slew::new();
+
+    // 5 m/s in steps of 100 ms: 1 m every 200 ms
+    let mut corrected = Vec::new();
+    for _ in 0..10 {
+        corrected.push(slew.step(&mut s, 0, 100_000, 5));
+    }
+    assert_eq!(corrected, vec![0, 1, 0, 1, 0, 1, 0, 1, 0, 1]);
+    assert_eq!(s.total_distance, 10_005);
+    assert_eq!(s.odometer_correction, 7);
+
+    for _ in 0..20 {
+        slew.step(&mut s, 0, 100_000, 5);
+    }
+    assert_eq!((s.total_distance, s.odometer_correction), (10_012, 0));
+    // the trip is not touched
+    assert_eq!(s.trips[0], 0);
+}
+
+#[test]
+fn slew_backwards_only_holds_back() {
+    let mut s = driven(10_000, 0);
+    s.odometer_correction = -3;
+    let mut slew = Slew::new();
+
+    // standing: the odometer never runs backwards
+    for _ in 0..20 {
+        assert_eq!(slew.step(&mut s, 0, 100_000, 5), 0);
+    }
+    assert_eq!(s.total_distance, 10_000);
+
+    // driving 2 m in this step (already added), both are held back
+    s.total_distance += 2;
+    assert_eq!(slew.step(&mut s, 2, 100_000, 5), -2);
+    assert_eq!((s.total_distance, s.odometer_correction), (10_000, -1));
+
+    // the budget saved while standing is at most one second (5 m)
+    s.total_distance += 2;
+    assert_eq!(slew.step(&mut s, 2, 100_000, 5), -1);
+    assert_eq!((s.total_distance, s.odometer_correction), (10_001, 0));
+}
+
+#[test]
+fn no_slew_with_ignition_off() {
+    let mut s = driven(10_000, 0);
+    s.odometer_correction = 12;
+    s.ignition = false;
+    let mut slew = Slew::new();
+
+    // the odometer is saved after every change with the ignition off, so nothing changes
+    for _ in 0..20 {
+        assert_eq!(slew.step(&mut s, 0, 100_000, 5), 0);
+    }
+    assert_eq!((s.total_distance, s.odometer_correction), (10_000, 12));
+
+    // and no budget was saved up meanwhile
+    s.ignition = true;
+    assert_eq!(slew.step(&mut s, 0, 100_000, 5), 0);
+    assert_eq!(slew.step(&mut s, 0, 100_000, 5), 1);
+}
+
+// the only test here that changes the global state
+#[test]
+fn serial_force_after_reject() {
+    state::update(|s| {
+        s.total_distance = 80_000;
+        s.trips[0] = 300;
+    });
+    assert_eq!(
+        run_serial_command("odometer force")
+            .iter()
+            .map(|l| l.as_str())
+            .collect::<Vec<_>>(),
+        vec!["ERR: no rejected odometer value"]
+    );
+
+    assert_eq!(simulator_odometer(10_000), Reconciled::Rejected(-70_000));
+    assert_eq!(
+        run_serial_command("odometer")[0].as_str(),
+        "odometer = 80000 m, trip = 300 m, drift = +0 m"
+    );
+    assert_eq!(
+        run_serial_command("odometer force")[0].as_str(),
+        "OK: odometer = 10000 m"
+    );
+    let s = state::snapshot();
+    assert_eq!((s.total_distance, s.trips[0]), (10_000, 300));
+}
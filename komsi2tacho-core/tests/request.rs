@@ -0,0 +1,92 @@
+// Host tests for the answers to the J1939 Request PGN
+
+use embassy_time::Instant;
+use komsi2tacho_core::address::{AddressClaim, DEFAULT_ADDRESS, Name};
+use komsi2tacho_core::frame::CanFrame;
+use komsi2tacho_core::request::{PGN_VEHICLE_ID, handle_request, requested_pgn};
+
+const TESTER: u8 = 0x17;
+
+// priority 6, PGN 59904 to `da`
+fn request(pgn: u32, da: u8) -> CanFrame {
+    let id = 0x18EA_0000 | (da as u32) << 8 | TESTER as u32;
+    CanFrame::new(id, &pgn.to_le_bytes()[..3])
+}
+
+fn claimed() -> (AddressClaim, Instant) {
+    let mut claim = AddressClaim::new(Name::new(), DEFAULT_ADDRESS);
+    claim.start(Instant::from_millis(0));
+    (claim, Instant::from_millis(1000))
+}
+
+#[test]
+fn request_pgn_is_decoded() {
+    assert_eq!(requested_pgn(&request(65254, 0xFF)), Some(65254));
+    assert_eq!(requested_pgn(&CanFrame::new(0x18FEC1EE, &[0; 8])), None);
+}
+
+#[test]
+fn known_pgn_is_answered() {
+    let (claim, now) = claimed();
+    let reply = handle_request(&request(65132, DEFAULT_ADDRESS), &claim, now);
+    assert_eq!(reply.map(|f| f.id()), Some(0x0CFE6CEE));
+
+    let reply = handle_request(&request(65217, 0xFF), &claim, now);
+    assert_eq!(reply.map(|f| f.pgn()), Some(65217));
+
+    // ETC2 also comes from our address
+    let reply = handle_request(&request(61445, DEFAULT_ADDRESS), &claim, now);
+    assert_eq!(reply.map(|f| f.id()), Some(0x18F005EE));
+}
+
+#[test]
+fn software_id_carries_the_version() {
+    let (claim, now) = claimed();
+    let reply = handle_request(&request(65242, DEFAULT_ADDRESS), &claim, now);
+    let version = env!("CARGO_PKG_VERSION")
+        .split('-')
+        .next()
+        .unwrap_or_default();
+    let expected = format!("\u{1}{version}*");
+    assert!(reply.is_some_and(|f| f.data().starts_with(expected.as_bytes())));
+}
+
+#[test]
+fn unknown_pgn_to_us_gets_a_nack() {
+    let (claim, now) = claimed();
+    let reply = handle_request(&request(PGN_VEHICLE_ID, DEFAULT_ADDRESS), &claim, now);
+    assert!(reply.is_some());
+    let nack = reply.unwrap_or(CanFrame::new(0, &[0; 8]));
+
+    // PGN 59392 to global, control byte 1 = NACK, requester and PGN
+    assert_eq!(nack.id(), 0x18E8FFEE);
+    assert_eq!(nack.data()[0], 1);
+    assert_eq!(nack.data()[4], TESTER);
+    assert_eq!(&nack.data()[5..8], &PGN_VEHICLE_ID.to_le_bytes()[..3]);
+}
+
+#[test]
+fn unknown_pgn_to_global_is_ignored() {
+    let (claim, now) = claimed();
+    assert_eq!(
+        handle_request(&request(PGN_VEHICLE_ID, 0xFF), &claim, now),
+        None
+    );
+}
+
+#[test]
+fn requests_for_other_ecus_are_ignored() {
+    let (claim, now) = claimed();
+    assert_eq!(handle_request(&request(65132, 0x00), &claim, now), None);
+}
+
+#[test]
+fn only_address_claim_is_answered_during_the_claim() {
+    let mut claim = AddressClaim::new(Name::new(), DEFAULT_ADDRESS);
+    claim.start(Instant::from_millis(0));
+    let now = Instant::from_millis(100);
+
+    assert_eq!(handle_request(&request(65132, 0xFF), &claim, now), None);
+    let reply = handle_request(&request(60928, 0xFF), &claim, now);
+    assert_eq!(reply.map(|f| f.id()), Some(0x18EEFFEE));
+}
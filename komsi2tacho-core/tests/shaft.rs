@@ -0,0 +1,118 @@
+// Host tests for the output shaft speed model
+
+use komsi2tacho_core::can::build_tachograph_frame;
+use komsi2tacho_core::config::Config;
+use komsi2tacho_core::shaft::{MAX_SHAFT_SPEED, ShaftModel};
+use komsi2tacho_core::speed::Speed;
+use komsi2tacho_core::state::VehicleState;
+
+fn kmh(kmh: u32) -> Speed {
+    Speed::from_kmh(kmh)
+}
+
+// with the 8 impulses per revolution of a KITAS sensor
+fn model(k_factor: u32, axle_ratio_milli: u32, tyre_circumference_mm: u32) -> ShaftModel {
+    ShaftModel {
+        k_factor,
+        axle_ratio_milli,
+        tyre_circumference_mm,
+        impulses_per_revolution: 8,
+    }
+}
+
+#[test]
+fn default_calibration() {
+    let shaft = ShaftModel::from_config(&Config::new());
+    assert_eq!(
+        shaft,
+        ShaftModel {
+            impulses_per_revolution: 1,
+            ..model(8000, 0, 0)
+        }
+    );
+    assert!(shaft.uses_k_factor());
+
+    // 8000 imp/km with 1 imp/revolution = 8000 rev/km, the old shaft_factor 1333
+    assert_eq!(shaft.revolutions_per_km_milli(), 8_000_000);
+    assert_eq!(shaft.shaft_speed(kmh(0)), 0);
+    assert_eq!(shaft.shaft_speed(kmh(1)), 133); // 133.3
+    assert_eq!(shaft.shaft_speed(kmh(30)), 4000);
+    assert_eq!(shaft.shaft_speed(kmh(50)), 6667); // 6666.67
+}
+
+#[test]
+fn impulses_per_revolution() {
+    // 8000 imp/km with 8 imp/revolution = 1000 rev/km
+    let shaft = model(8000, 0, 0);
+    assert_eq!(shaft.revolutions_per_km_milli(), 1_000_000);
+    assert_eq!(shaft.shaft_speed(kmh(60)), 1000);
+    assert_eq!(shaft.shaft_speed(kmh(1)), 17); // 16.67
+    assert_eq!(shaft.shaft_speed(kmh(50)), 833); // 833.33
+}
+
+#[test]
+fn k_factor_alone() {
+    // 6000 imp/km, 750 rev/km, 12.5 rpm per km/h
+    let shaft = model(6000, 0, 0);
+    assert_eq!(shaft.shaft_speed(kmh(1)), 13); // 12.5 rounds up
+    assert_eq!(shaft.shaft_speed(kmh(80)), 1000);
+
+    // ratio without tyre is not enough, k is used
+    assert_eq!(model(6000, 4100, 0).shaft_speed(kmh(80)), 1000);
+}
+
+#[test]
+fn ratio_and_tyre() {
+    // city bus: ratio 4.1, 295/80 R 22.5 with 3100 mm
+    let shaft = model(10_580, 4100, 3100);
+    assert_eq!(shaft.revolutions_per_km_milli(), 1_322_580);
+    assert_eq!(shaft.shaft_speed(kmh(80)), 1763); // 1763.44
+    assert_eq!(shaft.shaft_speed(kmh(50)), 1102); // 1102.15
+
+    // k fits 8 impulses per revolution
+    assert_eq!(shaft.impulses_per_revolution_milli(), 7999);
+
+    // k does not change the shaft speed if ratio and tyre are known
+    assert!(!shaft.uses_k_factor());
+    assert_eq!(model(8000, 4100, 3100).shaft_speed(kmh(80)), 1763);
+}
+
+#[test]
+fn saturation() {
+    let shaft = model(25_000, 0, 0);
+    assert_eq!(shaft.shaft_speed(kmh(150)), 7813); // 7812.5
+    assert_eq!(shaft.shaft_speed(kmh(155)), MAX_SHAFT_SPEED);
+    assert_eq!(
+        shaft.shaft_speed(Speed::from_raw(u32::MAX)),
+        MAX_SHAFT_SPEED
+    );
+}
+
+#[test]
+fn moving_shaft_never_stands_still() {
+    // 166 milli-rev per km, far below 1 rpm at 1 km/h
+    let shaft = model(4000, 1, 6000);
+    assert_eq!(shaft.shaft_speed(kmh(0)), 0);
+    assert_eq!(shaft.shaft_speed(kmh(1)), 1);
+}
+
+#[test]
+fn fractional_speed() {
+    let shaft = ShaftModel::from_config(&Config::new());
+    // 3.5 km/h = 466.67 rpm
+    assert_eq!(shaft.shaft_speed(Speed::from_raw(896)), 467);
+    // 1/256 km/h still turns the shaft
+    assert_eq!(shaft.shaft_speed(Speed::from_raw(1)), 1);
+}
+
+#[test]
+fn tachograph_frame_carries_shaft_speed() {
+    let state = VehicleState {
+        speed: kmh(60),
+        ..Default::default()
+    };
+    let frame = build_tachograph_frame(&state);
+    // 0.125 rpm per bit
+    let raw = u16::from_le_bytes([frame.data()[4], frame.data()[5]]);
+    assert_eq!(raw, 8000 * 8);
+}
@@ -0,0 +1,71 @@
+// Host tests for the fixed point speed
+
+use komsi2tacho_core::can::build_tachograph_frame;
+use komsi2tacho_core::commands::{KomsiParser, komsi_dispatch};
+use komsi2tacho_core::speed::Speed;
+use komsi2tacho_core::state::{self, VehicleState};
+
+#[test]
+fn conversions() {
+    assert_eq!(Speed::from_kmh(50).raw(), 50 * 256);
+    assert_eq!(Speed::from_raw(896).kmh(), 4); // 3.5 rounds up
+    assert_eq!(Speed::from_raw(895).kmh(), 3);
+    assert!(!Speed::ZERO.is_moving());
+    assert!(Speed::from_raw(1).is_moving());
+    // 0xFAFF is the highest valid value
+    assert_eq!(Speed::from_kmh(300).to_j1939(), 0xFAFF);
+}
+
+#[test]
+fn parse_komsi_digits() {
+    assert_eq!(Speed::parse(b"42"), Some(Speed::from_kmh(42)));
+    assert_eq!(Speed::parse(b"3.5"), Some(Speed::from_raw(896)));
+    assert_eq!(Speed::parse(b"3.50"), Some(Speed::from_raw(896)));
+    assert_eq!(Speed::parse(b".25"), Some(Speed::from_raw(64)));
+    assert_eq!(Speed::parse(b"4."), Some(Speed::from_kmh(4)));
+    // 0.1 km/h = 25.6/256, rounded
+    assert_eq!(Speed::parse(b"0.1"), Some(Speed::from_raw(26)));
+    // more decimals than we can show
+    assert_eq!(Speed::parse(b"1.0039062"), Some(Speed::from_raw(257)));
+
+    assert_eq!(Speed::parse(b""), None);
+    assert_eq!(Speed::parse(b"."), None);
+    assert_eq!(Speed::parse(b"1.2.3"), None);
+    assert_eq!(Speed::parse(b"99999999999"), None);
+}
+
+#[test]
+fn tachograph_frame_has_full_resolution() {
+    let state = VehicleState {
+        speed: Speed::from_raw(896),
+        ..VehicleState::new()
+    };
+    let frame = build_tachograph_frame(&state);
+    assert_eq!(u16::from_le_bytes([frame.data()[6], frame.data()[7]]), 896);
+    // vehicle motion is set
+    assert_eq!(frame.data()[0] >> 6, 0b01);
+}
+
+// the only test here that changes the global state
+#[test]
+fn decimal_speed_from_komsi() {
+    let mut parser = KomsiParser::new();
+    for &byte in b"A1\ny3.5\n" {
+        if let Some(cmd) = parser.push(byte) {
+            komsi_dispatch(cmd.letter, &cmd.digits);
+        }
+    }
+    assert_eq!(state::snapshot().speed, Speed::from_raw(896));
+
+    // whole km/h still go through the komsi crate
+    komsi_dispatch('y', b"12");
+    assert_eq!(state::snapshot().speed, Speed::from_kmh(12));
+
+    // the clamp also takes fractions
+    komsi_dispatch('y', b"125.5");
+    assert_eq!(state::snapshot().speed, Speed::from_kmh(125));
+
+    // no number, the speed stays
+    komsi_dispatch('y', b"1.2.3");
+    assert_eq!(state::snapshot().speed, Speed::from_kmh(125));
+}
@@ -0,0 +1,30 @@
+// Host tests for the vehicle state and its change notification
+
+use komsi2tacho_core::speed::Speed;
+use komsi2tacho_core::state;
+
+#[test]
+fn update_notifies_only_on_change() {
+    let mut rx = state::receiver().expect("receiver available");
+    assert!(rx.try_changed().is_none());
+
+    state::update(|s| s.max_speed = 80);
+    let changed = rx.try_changed().expect("change notified");
+    assert_eq!(changed.max_speed, 80);
+    assert!(changed.last_change.is_some());
+
+    // same value again, nobody is woken up
+    state::update(|s| s.max_speed = 80);
+    assert!(rx.try_changed().is_none());
+
+    // speed and odometer are updated together and read as one snapshot
+    state::update(|s| {
+        s.speed = Speed::from_kmh(42);
+        s.total_distance = 1000;
+    });
+    let snapshot = state::snapshot();
+    assert_eq!(
+        (snapshot.speed, snapshot.total_distance),
+        (Speed::from_kmh(42), 1000)
+    );
+}
@@ -0,0 +1,232 @@
+// Host tests for the calendar of the software clock
+
+use embassy_time::Instant;
+use komsi::KomsiDateTime;
+use komsi2tacho_core::time::{
+    REAL_TIME, SimClock, add_seconds, days_in_month, is_leap_year, parse_date, to_unix,
+};
+
+fn dt(year: u16, month: u8, day: u8, hour: u8, min: u8, sec: u8) -> KomsiDateTime {
+    KomsiDateTime {
+        year,
+        month,
+        day,
+        hour,
+        min,
+        sec,
+    }
+}
+
+fn plus(mut start: KomsiDateTime, secs: u64) -> KomsiDateTime {
+    add_seconds(&mut start, secs);
+    start
+}
+
+// the next day, counted the simple way
+fn next_day(year: u16, month: u8, day: u8) -> (u16, u8, u8) {
+    if day < days_in_month(year, month) {
+        (year, month, day + 1)
+    } else if month < 12 {
+        (year, month + 1, 1)
+    } else {
+        (year + 1, 1, 1)
+    }
+}
+
+#[test]
+fn leap_years() {
+    assert!(is_leap_year(2024));
+    assert!(is_leap_year(2000));
+    assert!(!is_leap_year(2023));
+    assert!(!is_leap_year(2100));
+    assert!(!is_leap_year(1900));
+
+    let days: u32 = (1..=12).map(|m| days_in_month(2024, m) as u32).sum();
+    assert_eq!(days, 366);
+    let days: u32 = (1..=12).map(|m| days_in_month(2100, m) as u32).sum();
+    assert_eq!(days, 365);
+    assert_eq!(days_in_month(2024, 13), 0);
+}
+
+#[test]
+fn within_the_day() {
+    assert_eq!(
+        plus(dt(2024, 5, 17, 8, 30, 0), 0),
+        dt(2024, 5, 17, 8, 30, 0)
+    );
+    assert_eq!(
+        plus(dt(2024, 5, 17, 8, 30, 0), 59),
+        dt(2024, 5, 17, 8, 30, 59)
+    );
+    assert_eq!(
+        plus(dt(2024, 5, 17, 8, 59, 59), 1),
+        dt(2024, 5, 17, 9, 0, 0)
+    );
+}
+
+#[test]
+fn every_midnight_from_1999_to_2101() {
+    let mut date = (1999, 1, 1);
+    while date.0 <= 2101 {
+        let (year, month, day) = date;
+        let expected = next_day(year, month, day);
+
+        // one second before midnight
+        let after = plus(dt(year, month, day, 23, 59, 59), 1);
+        assert_eq!(
+            (after.year, after.month, after.day),
+            expected,
+            "{year}-{month}-{day}"
+        );
+        assert_eq!((after.hour, after.min, after.sec), (0, 0, 0));
+
+        // a whole day from noon
+        let after = plus(dt(year, month, day, 12, 0, 0), 86_400);
+        assert_eq!(after, dt(expected.0, expected.1, expected.2, 12, 0, 0));
+
+        date = expected;
+    }
+}
+
+#[test]
+fn month_and_year_ends() {
+    assert_eq!(
+        plus(dt(2024, 1, 31, 23, 0, 0), 3600),
+        dt(2024, 2, 1, 0, 0, 0)
+    );
+    assert_eq!(
+        plus(dt(2024, 2, 28, 23, 0, 0), 3600),
+        dt(2024, 2, 29, 0, 0, 0)
+    );
+    assert_eq!(
+        plus(dt(2023, 2, 28, 23, 0, 0), 3600),
+        dt(2023, 3, 1, 0, 0, 0)
+    );
+    assert_eq!(
+        plus(dt(2024, 4, 30, 23, 0, 0), 3600),
+        dt(2024, 5, 1, 0, 0, 0)
+    );
+    assert_eq!(
+        plus(dt(2024, 12, 31, 22, 30, 0), 2 * 3600),
+        dt(2025, 1, 1, 0, 30, 0)
+    );
+    assert_eq!(
+        plus(dt(2100, 2, 28, 12, 0, 0), 86_400),
+        dt(2100, 3, 1, 12, 0, 0)
+    );
+    assert_eq!(
+        plus(dt(2000, 2, 28, 12, 0, 0), 86_400),
+        dt(2000, 2, 29, 12, 0, 0)
+    );
+}
+
+#[test]
+fn long_spans() {
+    // a leap day and a year later
+    assert_eq!(
+        plus(dt(2024, 2, 29, 0, 0, 0), 365 * 86_400),
+        dt(2025, 2, 28, 0, 0, 0)
+    );
+    // 10_000 days
+    assert_eq!(
+        plus(dt(2000, 1, 1, 0, 0, 0), 10_000 * 86_400),
+        dt(2027, 5, 19, 0, 0, 0)
+    );
+    // one second each for a whole year ends up in the same place
+    let mut t = dt(2023, 12, 31, 23, 59, 59);
+    for _ in 0..366 * 86_400 {
+        add_seconds(&mut t, 1);
+    }
+    assert_eq!(t, plus(dt(2023, 12, 31, 23, 59, 59), 366 * 86_400));
+    assert_eq!(t, dt(2024, 12, 31, 23, 59, 59));
+}
+
+fn at_ms(ms: u64) -> Instant {
+    Instant::from_millis(ms)
+}
+
+#[test]
+fn clock_runs_with_the_scale() {
+    let mut clock = SimClock::new();
+    assert_eq!(clock.now(at_ms(0), REAL_TIME), None);
+
+    clock.sync(&dt(2024, 12, 31, 23, 59, 0), at_ms(1000));
+    assert_eq!(
+        clock.now(at_ms(60_999), REAL_TIME),
+        Some(dt(2024, 12, 31, 23, 59, 59))
+    );
+    assert_eq!(
+        clock.now(at_ms(61_000), REAL_TIME),
+        Some(dt(2025, 1, 1, 0, 0, 0))
+    );
+    // 4 times faster, 2.5 times slower
+    assert_eq!(
+        clock.now(at_ms(16_000), 4000),
+        Some(dt(2025, 1, 1, 0, 0, 0))
+    );
+    assert_eq!(
+        clock.now(at_ms(151_000), 400),
+        Some(dt(2025, 1, 1, 0, 0, 0))
+    );
+}
+
+#[test]
+fn scale_is_measured_from_the_updates() {
+    let start = dt(2024, 6, 1, 12, 0, 0);
+    let mut clock = SimClock::new();
+    assert_eq!(clock.scale(0), REAL_TIME);
+    // a fixed scale wins
+    assert_eq!(clock.scale(2500), 2500);
+
+    // the simulation runs 3 times faster, KOMSI sends the time every 10 s
+    for i in 0..=30u64 {
+        clock.sync(&plus(start, i * 30), at_ms(i * 10_000));
+    }
+    let scale = clock.scale(0);
+    assert!((2990..=3000).contains(&scale), "{scale}");
+
+    // and the clock keeps up with the simulation until the next update
+    let now = clock.now(at_ms(310_000), scale).map(|t| to_unix(&t));
+    let expected = to_unix(&plus(start, 930));
+    assert!(now.is_some_and(|t| (expected - 1..=expected).contains(&t)));
+
+    // updates closer than 10 s are not measured
+    let mut clock = SimClock::new();
+    clock.sync(&start, at_ms(0));
+    clock.sync(&plus(start, 5), at_ms(1000));
+    assert_eq!(clock.scale(0), REAL_TIME);
+
+    // a time set back starts the measurement again
+    clock.sync(&start, at_ms(20_000));
+    assert_eq!(clock.scale(0), REAL_TIME);
+}
+
+#[test]
+fn started_clock_is_not_set() {
+    let mut clock = SimClock::new();
+    clock.start(to_unix(&dt(2025, 3, 1, 6, 0, 0)), at_ms(0));
+    assert!(!clock.is_set());
+    assert_eq!(
+        clock.now(at_ms(60_000), REAL_TIME),
+        Some(dt(2025, 3, 1, 6, 1, 0))
+    );
+
+    clock.sync(&dt(2025, 3, 2, 7, 0, 0), at_ms(70_000));
+    assert!(clock.is_set());
+    // a saved time does not overwrite a real one
+    clock.start(0, at_ms(80_000));
+    assert_eq!(
+        clock.now(at_ms(80_000), REAL_TIME),
+        Some(dt(2025, 3, 2, 7, 0, 10))
+    );
+}
+
+#[test]
+fn fallback_dates() {
+    assert_eq!(parse_date(20260213), Some(dt(2026, 2, 13, 0, 0, 0)));
+    assert_eq!(parse_date(20240229), Some(dt(2024, 2, 29, 0, 0, 0)));
+    assert_eq!(parse_date(20250229), None);
+    assert_eq!(parse_date(20251301), None);
+    assert_eq!(parse_date(20250100), None);
+    assert_eq!(parse_date(0), None);
+}
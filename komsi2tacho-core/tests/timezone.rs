@@ -0,0 +1,127 @@
+// Host tests for the time zone of the TimeDate frame
+
+use komsi::KomsiDateTime;
+use komsi2tacho_core::can::build_date_time_frame;
+use komsi2tacho_core::config::{self, Config};
+use komsi2tacho_core::time::{from_unix, to_unix};
+use komsi2tacho_core::timezone::{TimeZone, is_eu_summer_time, komsi_to_utc, offset_fields};
+
+fn dt(year: u16, month: u8, day: u8, hour: u8, min: u8, sec: u8) -> KomsiDateTime {
+    KomsiDateTime {
+        year,
+        month,
+        day,
+        hour,
+        min,
+        sec,
+    }
+}
+
+const BERLIN: TimeZone = TimeZone {
+    offset_min: 60,
+    eu_dst: true,
+};
+
+#[test]
+fn eu_summer_time_switches_at_one_utc() {
+    // 2024: last Sundays are 31 March and 27 October
+    assert!(!is_eu_summer_time(&dt(2024, 3, 31, 0, 59, 59)));
+    assert!(is_eu_summer_time(&dt(2024, 3, 31, 1, 0, 0)));
+    assert!(is_eu_summer_time(&dt(2024, 10, 27, 0, 59, 59)));
+    assert!(!is_eu_summer_time(&dt(2024, 10, 27, 1, 0, 0)));
+    // 2025: 30 March and 26 October
+    assert!(!is_eu_summer_time(&dt(2025, 3, 29, 12, 0, 0)));
+    assert!(is_eu_summer_time(&dt(2025, 3, 30, 1, 0, 0)));
+    assert!(!is_eu_summer_time(&dt(2025, 10, 26, 1, 0, 0)));
+    assert!(!is_eu_summer_time(&dt(2025, 1, 15, 12, 0, 0)));
+    assert!(is_eu_summer_time(&dt(2025, 7, 15, 12, 0, 0)));
+}
+
+#[test]
+fn local_time() {
+    assert_eq!(BERLIN.offset_at(&dt(2024, 1, 10, 12, 0, 0)), 60);
+    assert_eq!(BERLIN.offset_at(&dt(2024, 7, 10, 12, 0, 0)), 120);
+    assert_eq!(
+        BERLIN.to_local(&dt(2024, 7, 10, 23, 30, 0)),
+        dt(2024, 7, 11, 1, 30, 0)
+    );
+
+    // fixed offsets, also negative and not whole hours
+    let india = TimeZone {
+        offset_min: 330,
+        eu_dst: false,
+    };
+    assert_eq!(india.offset_at(&dt(2024, 7, 10, 12, 0, 0)), 330);
+    assert_eq!(
+        india.to_local(&dt(2024, 12, 31, 20, 0, 0)),
+        dt(2025, 1, 1, 1, 30, 0)
+    );
+    let newfoundland = TimeZone {
+        offset_min: -210,
+        eu_dst: false,
+    };
+    assert_eq!(
+        newfoundland.to_local(&dt(2024, 1, 1, 2, 0, 0)),
+        dt(2023, 12, 31, 22, 30, 0)
+    );
+    assert_eq!(offset_fields(330), (5, 30));
+    assert_eq!(offset_fields(-210), (-3, -30));
+    assert_eq!(offset_fields(0), (0, 0));
+}
+
+#[test]
+fn local_to_utc() {
+    assert_eq!(
+        BERLIN.to_utc(&dt(2024, 1, 10, 12, 0, 0)),
+        dt(2024, 1, 10, 11, 0, 0)
+    );
+    assert_eq!(
+        BERLIN.to_utc(&dt(2024, 7, 1, 1, 0, 0)),
+        dt(2024, 6, 30, 23, 0, 0)
+    );
+    // 02:30 exists twice on 27 October 2024, we take summer time
+    assert_eq!(
+        BERLIN.to_utc(&dt(2024, 10, 27, 2, 30, 0)),
+        dt(2024, 10, 27, 0, 30, 0)
+    );
+    // and does not exist on 31 March 2024
+    assert_eq!(
+        BERLIN.to_utc(&dt(2024, 3, 31, 2, 30, 0)),
+        dt(2024, 3, 31, 1, 30, 0)
+    );
+
+    // there and back again, every 10 minutes of a year, but the local hour that exists
+    // twice is always summer time
+    let start = to_unix(&dt(2024, 1, 1, 0, 0, 0));
+    let twice = to_unix(&dt(2024, 10, 27, 1, 0, 0))..to_unix(&dt(2024, 10, 27, 2, 0, 0));
+    for t in (start..start + 366 * 86_400).step_by(600) {
+        let expected = if twice.contains(&t) { t - 3600 } else { t };
+        assert_eq!(
+            to_unix(&BERLIN.to_utc(&BERLIN.to_local(&from_unix(t)))),
+            expected
+        );
+    }
+}
+
+// the only test here that changes the global config
+#[test]
+fn frame_and_komsi_time() {
+    let utc = dt(2024, 7, 10, 12, 0, 0);
+    // without a time zone KOMSI time is UTC and the offsets are 0
+    assert_eq!(komsi_to_utc(utc), utc);
+    let frame = build_date_time_frame(&utc);
+    assert_eq!(frame.data()[2], 12);
+    assert_eq!((frame.data()[6], frame.data()[7]), (125, 125));
+
+    config::set(Config {
+        timezone_offset_min: 60,
+        timezone_dst: true,
+        komsi_local_time: true,
+        ..Config::new()
+    });
+    assert_eq!(komsi_to_utc(dt(2024, 7, 10, 14, 0, 0)), utc);
+    let frame = build_date_time_frame(&utc);
+    // still UTC, local offset +2:00 (offset 125 per field)
+    assert_eq!(frame.data()[2], 12);
+    assert_eq!((frame.data()[6], frame.data()[7]), (125, 127));
+}
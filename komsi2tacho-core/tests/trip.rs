@@ -0,0 +1,85 @@
+// Host tests for the trip counters and their serial commands
+
+use komsi2tacho_core::can::build_hr_distance_frame;
+use komsi2tacho_core::commands::{komsi_dispatch, run_serial_command};
+use komsi2tacho_core::distance::{UNITS_PER_METER, add_distance};
+use komsi2tacho_core::state::{self, VehicleState};
+use komsi2tacho_core::trip::TRIP_COUNT;
+
+fn answer(command: &str) -> Vec<String> {
+    run_serial_command(command)
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+// trip distance in the HR frame, 5 m per bit in bytes 5-8
+fn sent_trip() -> u32 {
+    let frame = build_hr_distance_frame(&state::snapshot());
+    let d = frame.data();
+    u32::from_le_bytes([d[4], d[5], d[6], d[7]]) * 5
+}
+
+#[test]
+fn all_trips_count() {
+    let mut s = VehicleState {
+        trips: [100, 0, 5000],
+        ..VehicleState::new()
+    };
+    add_distance(&mut s, 20 * UNITS_PER_METER);
+    assert_eq!(s.trips, [120, 20, 5020]);
+    assert_eq!(s.total_distance, 20);
+}
+
+// the only test here that changes the global state and config
+#[test]
+fn serial_commands() {
+    state::update(|s| s.trips = [1000, 2000, 3000]);
+    assert_eq!(
+        answer("trip"),
+        vec![
+            "trip 1 = 1000 m (sent)",
+            "trip 2 = 2000 m",
+            "trip 3 = 3000 m"
+        ]
+    );
+    assert_eq!(sent_trip(), 1000);
+
+    // another trip is sent
+    assert_eq!(answer("trip select 3"), vec!["OK: trip_selected = 3"]);
+    assert_eq!(sent_trip(), 3000);
+    assert!(answer("trip select 4")[0].starts_with("ERR"));
+
+    // reset without number is the sent one
+    answer("trip reset");
+    assert_eq!(state::snapshot().trips, [1000, 2000, 0]);
+    answer("trip reset 1");
+    assert_eq!(state::snapshot().trips, [0, 2000, 0]);
+
+    assert_eq!(
+        answer("trip set 2 12345"),
+        vec![
+            "OK: trip 1 = 0 m",
+            "OK: trip 2 = 12345 m",
+            "OK: trip 3 = 0 m"
+        ]
+    );
+    assert_eq!(answer("trip set 0 1")[0], "ERR: trip must be 1..3");
+    assert_eq!(answer("trip set 2 much")[0], "ERR: not a number: much");
+    assert_eq!(state::snapshot().trips[1], 12345);
+
+    answer("trip reset all");
+    assert_eq!(state::snapshot().trips, [0; TRIP_COUNT]);
+    assert!(answer("trip clear")[0].starts_with("ERR"));
+
+    // resets from KOMSI, trip 3 is still the sent one
+    state::update(|s| s.trips = [1000, 2000, 3000]);
+    komsi_dispatch('U', b"0");
+    assert_eq!(state::snapshot().trips, [1000, 2000, 0]);
+    komsi_dispatch('U', b"1");
+    assert_eq!(state::snapshot().trips, [0, 2000, 0]);
+    komsi_dispatch('U', b"5");
+    assert_eq!(state::snapshot().trips, [0, 2000, 0]);
+    komsi_dispatch('U', b"9");
+    assert_eq!(state::snapshot().trips, [0; TRIP_COUNT]);
+}
@@ -0,0 +1,83 @@
+//! A file as flash for the persistent odometer and config.
+
+use heapless::String;
+use komsi2tacho_core::hal::{SECTOR_SIZE, Storage, StorageError};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+
+/// Behaves like NOR flash: erased bytes are 0xFF, a write can only clear bits
+pub struct FlashFile {
+    file: File,
+    sectors: u32,
+}
+
+impl FlashFile {
+    /// Opens the file, a new file (or one of another size) is created in erased state
+    pub fn open(path: &str, sectors: u32) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let capacity = (sectors * SECTOR_SIZE) as u64;
+        if file.metadata()?.len() != capacity {
+            file.set_len(0)?;
+            file.write_all_at(&vec![0xFF; capacity as usize], 0)?;
+        }
+        Ok(Self { file, sectors })
+    }
+
+    fn check(&self, offset: u32, len: usize) -> Result<(), StorageError> {
+        if offset as usize + len > self.capacity() as usize {
+            return Err(StorageError::OutOfBounds);
+        }
+        Ok(())
+    }
+}
+
+fn to_storage_error(e: io::Error) -> StorageError {
+    let mut text: String<32> = String::new();
+    for c in e.kind().to_string().chars() {
+        if text.push(c).is_err() {
+            break;
+        }
+    }
+    StorageError::Other(text)
+}
+
+impl Storage for FlashFile {
+    fn capacity(&self) -> u32 {
+        self.sectors * SECTOR_SIZE
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), StorageError> {
+        self.check(offset, buf.len())?;
+        self.file
+            .read_exact_at(buf, offset as u64)
+            .map_err(to_storage_error)
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), StorageError> {
+        self.check(offset, data.len())?;
+        let mut flash = vec![0u8; data.len()];
+        self.file
+            .read_exact_at(&mut flash, offset as u64)
+            .map_err(to_storage_error)?;
+        for (old, new) in flash.iter_mut().zip(data) {
+            *old &= new;
+        }
+        self.file
+            .write_all_at(&flash, offset as u64)
+            .and_then(|()| self.file.sync_data())
+            .map_err(to_storage_error)
+    }
+
+    fn erase_sector(&mut self, offset: u32) -> Result<(), StorageError> {
+        self.check(offset, SECTOR_SIZE as usize)?;
+        self.file
+            .write_all_at(&[0xFF; SECTOR_SIZE as usize], offset as u64)
+            .map_err(to_storage_error)
+    }
+}
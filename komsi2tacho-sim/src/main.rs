@@ -0,0 +1,275 @@
+//! Komsi2Tacho simulator
+//!
+//! Runs the same tasks as the ESP32-C6 firmware on a Linux PC. The KOMSI commands are read
+//! from a pseudo terminal, the J1939 frames are sent to a SocketCAN interface.
+//!
+//! ```text
+//! sudo ip link add dev vcan0 type vcan && sudo ip link set up vcan0
+//! komsi2tacho-sim --can vcan0 --link /tmp/komsi
+//! candump vcan0
+//! ```
+
+mod flashfile;
+mod pty;
+mod socketcan;
+
+use embassy_executor::Spawner;
+use flashfile::FlashFile;
+use komsi2tacho_core::address::{self, Name};
+use komsi2tacho_core::can::{
+    can_manager, date_time_loop, engine_loop, hr_distance_loop, tachograph_loop, transmission_loop,
+};
+use komsi2tacho_core::commands::komsi_loop;
+use komsi2tacho_core::config::{self, ConfigStore, config_loop};
+use komsi2tacho_core::distance::distance_loop;
+use komsi2tacho_core::driving_time::driving_time_loop;
+use komsi2tacho_core::fault::fault_loop;
+use komsi2tacho_core::odometer::{self, OdometerStore, odometer_loop};
+use komsi2tacho_core::overspeed::overspeed_loop;
+use komsi2tacho_core::power::power_loop;
+use komsi2tacho_core::time::start_clock;
+use pty::Pty;
+use socketcan::SocketCan;
+
+const USAGE: &str = "Usage: komsi2tacho-sim [--can <interface>] [--link <path>] [--address <sa>]
+                       [--identity <n>] [--odometer <file>] [--config <file>]
+
+  --can <interface>  SocketCAN interface for the J1939 frames (default: vcan0)
+  --link <path>      creates a symlink to the KOMSI pty, e.g. /tmp/komsi
+  --address <sa>     preferred J1939 source address, decimal or 0x..
+                     (default: source_address of the config, 0xEE)
+  --identity <n>     identity number of the J1939 NAME, 0 to 2097151; give every
+                     simulator on one bus its own (default: the process id)
+  --odometer <file>  keeps the odometer in this file like the firmware in flash
+  --config <file>    keeps the config (:set ...) in this file like the firmware in flash";
+
+// the same sizes as the flash regions of the firmware
+const ODOMETER_SECTORS: u32 = 4;
+const CONFIG_SECTORS: u32 = 2;
+
+struct Options {
+    can_interface: String,
+    link: Option<String>,
+    address: Option<u8>,
+    identity_number: u32,
+    odometer: Option<String>,
+    config: Option<String>,
+}
+
+impl Options {
+    fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            can_interface: "vcan0".into(),
+            link: None,
+            address: None,
+            identity_number: std::process::id() & 0x1F_FFFF,
+            odometer: None,
+            config: None,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--can" => options.can_interface = args.next().ok_or("--can needs a value")?,
+                "--link" => options.link = Some(args.next().ok_or("--link needs a value")?),
+                "--address" => {
+                    let value = args.next().ok_or("--address needs a value")?;
+                    options.address = Some(
+                        parse_address(&value).ok_or_else(|| format!("invalid address: {value}"))?,
+                    );
+                }
+                "--identity" => {
+                    let value = args.next().ok_or("--identity needs a value")?;
+                    options.identity_number = value
+                        .parse()
+                        .ok()
+                        .filter(|n| *n <= 0x1F_FFFF)
+                        .ok_or_else(|| format!("invalid identity number: {value}"))?;
+                }
+                "--odometer" => {
+                    options.odometer = Some(args.next().ok_or("--odometer needs a value")?);
+                }
+                "--config" => options.config = Some(args.next().ok_or("--config needs a value")?),
+                _ => return Err(format!("unknown argument: {arg}")),
+            }
+        }
+        Ok(options)
+    }
+}
+
+// 0xFE and 0xFF are the null and the global address
+fn parse_address(value: &str) -> Option<u8> {
+    let address = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u8::from_str_radix(hex, 16).ok()?,
+        None => value.parse().ok()?,
+    };
+    (address < 0xFE).then_some(address)
+}
+
+#[embassy_executor::task]
+async fn can_manager_task(mut bus: SocketCan) {
+    can_manager(&mut bus).await;
+}
+
+#[embassy_executor::task]
+async fn komsi_task(mut port: Pty) {
+    komsi_loop(&mut port).await;
+}
+
+#[embassy_executor::task]
+async fn hr_distance_task() {
+    hr_distance_loop().await;
+}
+
+#[embassy_executor::task]
+async fn distance_task() {
+    distance_loop().await;
+}
+
+#[embassy_executor::task]
+async fn driving_time_task() {
+    driving_time_loop().await;
+}
+
+#[embassy_executor::task]
+async fn fault_task() {
+    fault_loop().await;
+}
+
+#[embassy_executor::task]
+async fn overspeed_task() {
+    overspeed_loop().await;
+}
+
+#[embassy_executor::task]
+async fn tachograph_task() {
+    tachograph_loop().await;
+}
+
+#[embassy_executor::task]
+async fn date_time_task() {
+    date_time_loop().await;
+}
+
+#[embassy_executor::task]
+async fn engine_task() {
+    engine_loop().await;
+}
+
+#[embassy_executor::task]
+async fn transmission_task() {
+    transmission_loop().await;
+}
+
+#[embassy_executor::task]
+async fn odometer_task(store: OdometerStore<FlashFile>) {
+    odometer_loop(store).await;
+}
+
+#[embassy_executor::task]
+async fn config_task(store: ConfigStore<FlashFile>) {
+    config_loop(store).await;
+}
+
+#[embassy_executor::task]
+async fn power_task() {
+    power_loop().await;
+}
+
+fn exit_with_error(msg: &str) -> ! {
+    eprintln!("komsi2tacho-sim: {msg}");
+    std::process::exit(1);
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let options = Options::from_args(std::env::args().skip(1))
+        .unwrap_or_else(|e| exit_with_error(&format!("{e}\n\n{USAGE}")));
+
+    let bus = SocketCan::open(&options.can_interface).unwrap_or_else(|e| {
+        exit_with_error(&format!(
+            "cannot open CAN interface {}: {e}",
+            options.can_interface
+        ))
+    });
+    let port = Pty::open().unwrap_or_else(|e| exit_with_error(&format!("cannot open pty: {e}")));
+
+    if let Some(link) = &options.link {
+        let _ = std::fs::remove_file(link);
+        if let Err(e) = std::os::unix::fs::symlink(port.slave_path(), link) {
+            exit_with_error(&format!("cannot create link {link}: {e}"));
+        }
+    }
+
+    if let Err(e) = bus.start_reader().and_then(|()| port.start_reader()) {
+        exit_with_error(&format!("cannot start reader threads: {e}"));
+    }
+
+    // the config first, everything else depends on it
+    let config_store = options.config.as_ref().map(|path| {
+        FlashFile::open(path, CONFIG_SECTORS)
+            .map_err(|e| format!("cannot open config file {path}: {e}"))
+            .and_then(|file| {
+                config::restore(file).map_err(|e| format!("cannot read config file {path}: {e:?}"))
+            })
+            .unwrap_or_else(|e| exit_with_error(&e))
+    });
+
+    let address = options
+        .address
+        .unwrap_or(config::get().source_address as u8);
+    address::configure(
+        Name::new().with_identity_number(options.identity_number),
+        address,
+    );
+
+    // odometer from the last run, before the CAN tasks send the first values
+    let odometer_store = options.odometer.as_ref().map(|path| {
+        FlashFile::open(path, ODOMETER_SECTORS)
+            .map_err(|e| format!("cannot open odometer file {path}: {e}"))
+            .and_then(|file| {
+                odometer::restore(file)
+                    .map_err(|e| format!("cannot read odometer file {path}: {e:?}"))
+            })
+            .unwrap_or_else(|e| exit_with_error(&e))
+    });
+
+    // like the firmware, the clock counts on from the last saved time until KOMSI sets it
+    start_clock(
+        odometer_store
+            .as_ref()
+            .and_then(|s| s.last())
+            .and_then(|r| r.time),
+    );
+
+    println!(
+        "Komsi2Tacho Simulator Version {} started",
+        env!("CARGO_PKG_VERSION")
+    );
+    println!("KOMSI port: {}", port.slave_path());
+    println!("CAN interface: {}", options.can_interface);
+    println!(
+        "J1939 address: {:#04X}, identity number {}",
+        address, options.identity_number
+    );
+
+    spawner.must_spawn(komsi_task(port));
+    spawner.must_spawn(can_manager_task(bus));
+    spawner.must_spawn(power_task()); // switches the broadcasts with the ignition
+    spawner.must_spawn(distance_task()); // integrates the distance from the speed
+    spawner.must_spawn(driving_time_task()); // driving times for the warnings of driver 1
+    spawner.must_spawn(overspeed_task()); // overspeed flag and event log
+    spawner.must_spawn(fault_task()); // ends the injected tachograph faults
+    spawner.must_spawn(hr_distance_task()); // sends distance info to Tacho
+    spawner.must_spawn(tachograph_task()); // sends speed data to Tacho
+    spawner.must_spawn(date_time_task()); // sends datetime info to Tacho
+    spawner.must_spawn(engine_task()); // sends engine speed (EEC1)
+    spawner.must_spawn(transmission_task()); // sends the gear (ETC2)
+    if let Some(store) = odometer_store {
+        spawner.must_spawn(odometer_task(store)); // saves the odometer to the file
+    }
+    if let Some(store) = config_store {
+        spawner.must_spawn(config_task(store)); // saves config changes to the file
+    }
+}
@@ -0,0 +1,151 @@
+//! Pseudo terminal as KOMSI port.
+//!
+//! The simulator plugins (TheBus2Komsi, Omsi2Komsi) or a terminal program open the
+//! slave side (e.g. /dev/pts/3), we read and write the master side.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+use heapless::Vec;
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+
+// bytes received from the pty, filled by the reader thread
+static SERIAL_RX_CHANNEL: Channel<CriticalSectionRawMutex, Vec<u8, 64>, 8> = Channel::new();
+
+pub struct Pty {
+    master: OwnedFd,
+    // we keep the slave open, otherwise reading the master fails with EIO
+    // as long as no program has opened the port
+    _slave: File,
+    slave_path: String,
+    pending: std::vec::Vec<u8>,
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+impl Pty {
+    pub fn open() -> io::Result<Self> {
+        // SAFETY: plain libc calls, every returned fd is owned by an OwnedFd/File
+        unsafe {
+            let fd = check(libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY))?;
+            let master = OwnedFd::from_raw_fd(fd);
+            check(libc::grantpt(fd))?;
+            check(libc::unlockpt(fd))?;
+
+            let mut name = [0 as libc::c_char; 128];
+            let result = libc::ptsname_r(fd, name.as_mut_ptr(), name.len());
+            if result != 0 {
+                return Err(io::Error::from_raw_os_error(result));
+            }
+            let slave_path = CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned();
+
+            let slave = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(libc::O_NOCTTY)
+                .open(&slave_path)?;
+
+            // raw mode: no echo, no line buffering, no CR/LF translation
+            let mut tio: libc::termios = std::mem::zeroed();
+            check(libc::tcgetattr(slave.as_raw_fd(), &mut tio))?;
+            libc::cfmakeraw(&mut tio);
+            check(libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &tio))?;
+
+            // writes must never block the executor, see write()
+            let flags = check(libc::fcntl(fd, libc::F_GETFL))?;
+            check(libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK))?;
+
+            Ok(Self {
+                master,
+                _slave: slave,
+                slave_path,
+                pending: std::vec::Vec::new(),
+            })
+        }
+    }
+
+    /// Path of the port the simulator plugins have to open
+    pub fn slave_path(&self) -> &str {
+        &self.slave_path
+    }
+
+    /// Starts the thread that reads from the pty and feeds the KOMSI task
+    pub fn start_reader(&self) -> io::Result<()> {
+        let fd = self.master.try_clone()?;
+        std::thread::Builder::new()
+            .name("pty-reader".into())
+            .spawn(move || read_loop(&fd))?;
+        Ok(())
+    }
+}
+
+fn read_loop(fd: &OwnedFd) {
+    let mut buffer = [0u8; 64];
+    loop {
+        let mut poll_fd = libc::pollfd {
+            fd: fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: poll_fd and buffer live for the duration of the calls
+        let len = unsafe {
+            if libc::poll(&mut poll_fd, 1, -1) < 0 {
+                continue;
+            }
+            libc::read(fd.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len())
+        };
+
+        if len > 0 {
+            let mut chunk = Vec::new();
+            let _ = chunk.extend_from_slice(&buffer[..len as usize]);
+            if SERIAL_RX_CHANNEL.try_send(chunk).is_err() {
+                eprintln!("KOMSI input dropped (channel full)");
+            }
+        } else if io::Error::last_os_error().kind() != io::ErrorKind::WouldBlock {
+            // e.g. EIO while the port is re-opened, don't spin
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+    }
+}
+
+impl ErrorType for Pty {
+    type Error = ErrorKind;
+}
+
+impl Read for Pty {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.pending.is_empty() {
+            let chunk = SERIAL_RX_CHANNEL.receive().await;
+            self.pending.extend_from_slice(&chunk);
+        }
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for Pty {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        // SAFETY: buf is valid for buf.len() bytes
+        let len = unsafe { libc::write(self.master.as_raw_fd(), buf.as_ptr().cast(), buf.len()) };
+        if len >= 0 {
+            Ok(len as usize)
+        } else if io::Error::last_os_error().kind() == io::ErrorKind::WouldBlock {
+            // nobody reads the port, we drop the output like the ESP32 does without USB host
+            Ok(buf.len())
+        } else {
+            Err(ErrorKind::Other)
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! SocketCAN interface (e.g. vcan0) as CAN bus.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Timer};
+use heapless::String;
+use komsi2tacho_core::frame::CanFrame;
+use komsi2tacho_core::hal::{CanError, CanReceiver, CanTransmitter};
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+// frames received from the bus, filled by the reader thread
+static CAN_RX_CHANNEL: Channel<CriticalSectionRawMutex, CanFrame, 32> = Channel::new();
+
+pub struct SocketCan {
+    fd: OwnedFd,
+}
+
+impl SocketCan {
+    /// Opens a raw CAN socket on the given interface
+    pub fn open(interface: &str) -> io::Result<Self> {
+        let name = CString::new(interface)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid interface name"))?;
+
+        // SAFETY: plain libc calls, the socket is owned by an OwnedFd
+        unsafe {
+            let index = libc::if_nametoindex(name.as_ptr());
+            if index == 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let fd = libc::socket(libc::PF_CAN, libc::SOCK_RAW, libc::CAN_RAW);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = OwnedFd::from_raw_fd(fd);
+
+            let mut addr: libc::sockaddr_can = std::mem::zeroed();
+            addr.can_family = libc::AF_CAN as libc::sa_family_t;
+            addr.can_ifindex = index as libc::c_int;
+            let result = libc::bind(
+                fd.as_raw_fd(),
+                (&raw const addr).cast(),
+                size_of::<libc::sockaddr_can>() as libc::socklen_t,
+            );
+            if result < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Self { fd })
+        }
+    }
+
+    /// Starts the thread that reads from the socket and feeds the CAN manager
+    pub fn start_reader(&self) -> io::Result<()> {
+        let fd = self.fd.try_clone()?;
+        std::thread::Builder::new()
+            .name("can-reader".into())
+            .spawn(move || read_loop(&fd))?;
+        Ok(())
+    }
+}
+
+fn read_loop(fd: &OwnedFd) {
+    loop {
+        // SAFETY: can_frame is plain old data, read() writes at most its size
+        let (len, can_frame) = unsafe {
+            let mut can_frame: libc::can_frame = std::mem::zeroed();
+            let len = libc::read(
+                fd.as_raw_fd(),
+                (&raw mut can_frame).cast(),
+                size_of::<libc::can_frame>(),
+            );
+            (len, can_frame)
+        };
+        if len < 0 {
+            eprintln!("CAN read error: {}", io::Error::last_os_error());
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            continue;
+        }
+
+        // error and remote frames are not used with J1939
+        if can_frame.can_id & (libc::CAN_ERR_FLAG | libc::CAN_RTR_FLAG) != 0 {
+            continue;
+        }
+        let id = if can_frame.can_id & libc::CAN_EFF_FLAG != 0 {
+            can_frame.can_id & libc::CAN_EFF_MASK
+        } else {
+            can_frame.can_id & libc::CAN_SFF_MASK
+        };
+        let dlc = (can_frame.can_dlc as usize).min(8);
+        let frame = CanFrame::new(id, &can_frame.data[..dlc]);
+
+        if CAN_RX_CHANNEL.try_send(frame).is_err() {
+            eprintln!("CAN frame dropped (channel full)");
+        }
+    }
+}
+
+impl CanTransmitter for SocketCan {
+    async fn transmit(&mut self, frame: &CanFrame) -> Result<(), CanError> {
+        // SAFETY: can_frame is plain old data, write() reads exactly its size
+        let len = unsafe {
+            let mut can_frame: libc::can_frame = std::mem::zeroed();
+            can_frame.can_id = frame.id() | libc::CAN_EFF_FLAG;
+            can_frame.can_dlc = frame.data().len() as u8;
+            can_frame.data[..frame.data().len()].copy_from_slice(frame.data());
+            libc::write(
+                self.fd.as_raw_fd(),
+                (&raw const can_frame).cast(),
+                size_of::<libc::can_frame>(),
+            )
+        };
+        if len < 0 {
+            let mut err_msg: String<32> = String::new();
+            for c in io::Error::last_os_error().to_string().chars() {
+                if err_msg.push(c).is_err() {
+                    break;
+                }
+            }
+            return Err(CanError::Other(err_msg));
+        }
+        Ok(())
+    }
+
+    async fn restart(&mut self) {
+        // nothing to reset on SocketCAN, we only keep the timing of the ESP32
+        Timer::after(Duration::from_millis(1000)).await;
+    }
+}
+
+impl CanReceiver for SocketCan {
+    async fn receive(&mut self) -> Result<CanFrame, CanError> {
+        Ok(CAN_RX_CHANNEL.receive().await)
+    }
+}
@@ -1,20 +1,30 @@
 #![no_std]
 #![no_main]
 
-use defmt::info;
+use defmt::{error, info};
 use embassy_executor::Spawner;
 use embassy_time::{Duration, Timer};
 use esp_hal::clock::CpuClock;
+use esp_hal::efuse::Efuse;
 use esp_hal::gpio::{Input, InputConfig, Io, Level, Output, OutputConfig, Pull};
 use esp_hal::timer::timg::TimerGroup;
 use esp_hal::twai::{BaudRate, TwaiConfiguration, TwaiMode};
 use esp_hal::usb_serial_jtag::UsbSerialJtag;
-use komsi::KomsiDateTime;
+use esp_storage::FlashStorage;
+use komsi2tacho::address::{self, Name, identity_number_from_mac};
 use komsi2tacho::can::{
-    can_manager_task, can_self_test_task, date_time_task, hr_distance_task, tachograph_task,
+    can_manager_task, can_self_test_task, date_time_task, distance_task, driving_time_task,
+    engine_task, fault_task, hr_distance_task, overspeed_task, power_task, tachograph_task,
+    transmission_task,
 };
 use komsi2tacho::commands::{komsi_task, usb_write};
-use komsi2tacho::time::sync_system_time;
+use komsi2tacho::config;
+use komsi2tacho::odometer;
+use komsi2tacho::storage::{
+    CONFIG_OFFSET, CONFIG_SIZE, EspFlashStorage, ODOMETER_OFFSET, ODOMETER_SIZE, config_task,
+    init_flash, odometer_task,
+};
+use komsi2tacho::time::start_clock;
 
 #[panic_handler]
 fn panic(_: &core::panic::PanicInfo) -> ! {
@@ -30,15 +40,39 @@ async fn main(spawner: Spawner) -> ! {
     let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
     let peripherals = esp_hal::init(config);
 
-    // Set initial date and time
-    sync_system_time(KomsiDateTime {
-        year: 2026,
-        month: 2,
-        day: 13,
-        hour: 8,
-        min: 0,
-        sec: 0,
-    });
+    // the config first, everything else depends on it
+    init_flash(FlashStorage::new(peripherals.FLASH));
+    let config_store = match config::restore(EspFlashStorage::new(CONFIG_OFFSET, CONFIG_SIZE)) {
+        Ok(store) => Some(store),
+        Err(e) => {
+            error!("Config storage not available, using defaults: {:?}", e);
+            None
+        }
+    };
+    let settings = config::get();
+
+    // J1939 NAME unique per chip and preferred address, use ":set source_address" if another ECU
+    // on the bus uses 0xEE
+    let name = Name::new().with_identity_number(identity_number_from_mac(Efuse::mac_address()));
+    address::configure(name, settings.source_address as u8);
+
+    // odometer from the last power cycle, before the CAN tasks send the first values
+    let odometer_store =
+        match odometer::restore(EspFlashStorage::new(ODOMETER_OFFSET, ODOMETER_SIZE)) {
+            Ok(store) => Some(store),
+            Err(e) => {
+                error!("Odometer storage not available: {:?}", e);
+                None
+            }
+        };
+
+    // the clock counts on from the last saved time (or fallback_date) until KOMSI sets it
+    start_clock(
+        odometer_store
+            .as_ref()
+            .and_then(|s| s.last())
+            .and_then(|r| r.time),
+    );
 
     // USB Serial JTAG initialization
     let usb_serial = UsbSerialJtag::new(peripherals.USB_DEVICE).into_async();
@@ -79,11 +113,19 @@ async fn main(spawner: Spawner) -> ! {
     // It is even possible that the order of ports/pins in the parameters is not RX TX (as currently) but exactly the opposite.
     // That's the curse of an unstable API
     // as long as we stay with our current software versions, we are fine.
+
+    // the bitrate is only read at startup, a change needs a reset
+    let baud_rate = match settings.can_bitrate {
+        125_000 => BaudRate::B125K,
+        500_000 => BaudRate::B500K,
+        1_000_000 => BaudRate::B1000K,
+        _ => BaudRate::B250K,
+    };
     let can_config = TwaiConfiguration::new(
         peripherals.TWAI0,
         peripherals.GPIO7, // TWAI_RX
         peripherals.GPIO6, // TWAI_TX
-        BaudRate::B250K,
+        baud_rate,
         twai_mode,
     );
 
@@ -99,14 +141,28 @@ async fn main(spawner: Spawner) -> ! {
     } else {
         info!("No Debug Mode enabled, CAN Mode: Normal operation");
         spawner.spawn(can_manager_task(twai)).unwrap();
-        spawner.spawn(hr_distance_task()).unwrap(); // sends distance info to Tacho and updates values
+        spawner.spawn(power_task()).unwrap(); // switches the broadcasts with the ignition
+        spawner.spawn(distance_task()).unwrap(); // integrates the distance from the speed
+        spawner.spawn(driving_time_task()).unwrap(); // driving times for the warnings of driver 1
+        spawner.spawn(overspeed_task()).unwrap(); // overspeed flag and event log
+        spawner.spawn(fault_task()).unwrap(); // ends the injected tachograph faults
+        spawner.spawn(hr_distance_task()).unwrap(); // sends distance info to Tacho
         spawner.spawn(tachograph_task()).unwrap(); // sends speed data to Tacho
         spawner.spawn(date_time_task()).unwrap(); // sends datetime info to Tacho
+        spawner.spawn(engine_task()).unwrap(); // sends engine speed (EEC1)
+        spawner.spawn(transmission_task()).unwrap(); // sends the gear (ETC2)
+        if let Some(store) = odometer_store {
+            spawner.spawn(odometer_task(store)).unwrap(); // saves the odometer to flash
+        }
+    }
+    if let Some(store) = config_store {
+        spawner.spawn(config_task(store)).unwrap(); // saves config changes to flash
     }
 
     info!(
-        "Komsi2Tacho Version {}: TWAI/CAN initialized (250k, Mode: {:?}).",
+        "Komsi2Tacho Version {}: TWAI/CAN initialized ({} bit/s, Mode: {:?}).",
         env!("CARGO_PKG_VERSION"),
+        settings.can_bitrate,
         twai_mode
     );
 
@@ -3,4 +3,10 @@ extern crate alloc;
 
 pub mod can;
 pub mod commands;
-pub mod time;
+pub mod storage;
+
+// the logic lives in the hardware independent core, we only add the ESP32 drivers
+pub use komsi2tacho_core::address;
+pub use komsi2tacho_core::config;
+pub use komsi2tacho_core::odometer;
+pub use komsi2tacho_core::time;
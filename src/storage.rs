@@ -0,0 +1,107 @@
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use esp_storage::FlashStorage;
+use heapless::String;
+use komsi2tacho_core::config::{ConfigStore, config_loop};
+use komsi2tacho_core::hal::{SECTOR_SIZE, Storage, StorageError};
+use komsi2tacho_core::odometer::{OdometerStore, odometer_loop};
+
+// We use the "nvs" partition (0x9000, 6 sectors) of the default espflash partition table
+// for the odometer and the config. We do not use ESP-IDF, so nobody else writes there.
+pub const ODOMETER_OFFSET: u32 = 0x9000;
+pub const ODOMETER_SIZE: u32 = 4 * SECTOR_SIZE;
+pub const CONFIG_OFFSET: u32 = ODOMETER_OFFSET + ODOMETER_SIZE;
+pub const CONFIG_SIZE: u32 = 2 * SECTOR_SIZE;
+
+// There is only one flash peripheral, the regions share it. Whoever uses it takes it out of
+// the cell and puts it back afterwards, so the critical section is only as long as the take.
+// An empty cell works like a busy flag: the Storage trait is blocking and we cannot wait.
+// This keeps interrupts on during an erase, but it does not make the erase asynchronous: it
+// still blocks the executor for tens of milliseconds and the CAN frames of that moment go
+// out late. That happens once per odometer_save_s, after ignition off and on ":set",
+// so we accept it.
+static FLASH: Mutex<CriticalSectionRawMutex, Cell<Option<FlashStorage<'static>>>> =
+    Mutex::new(Cell::new(None));
+
+fn storage_error(text: &str) -> StorageError {
+    StorageError::Other(String::try_from(text).unwrap_or_default())
+}
+
+/// Hands the flash to the regions, call it once before `EspFlashStorage::new`
+pub fn init_flash(flash: FlashStorage<'static>) {
+    FLASH.lock(|f| f.set(Some(flash)));
+}
+
+fn with_flash<R>(
+    f: impl FnOnce(&mut FlashStorage<'static>) -> Result<R, StorageError>,
+) -> Result<R, StorageError> {
+    let mut flash = FLASH
+        .lock(|f| f.take())
+        .ok_or_else(|| storage_error("flash busy or not initialized"))?;
+    let result = f(&mut flash);
+    FLASH.lock(|f| f.set(Some(flash)));
+    result
+}
+
+/// A region of the ESP32 flash
+pub struct EspFlashStorage {
+    offset: u32,
+    size: u32,
+}
+
+impl EspFlashStorage {
+    pub fn new(offset: u32, size: u32) -> Self {
+        Self { offset, size }
+    }
+
+    fn check(&self, offset: u32, len: usize) -> Result<u32, StorageError> {
+        if offset as usize + len > self.size as usize {
+            return Err(StorageError::OutOfBounds);
+        }
+        Ok(self.offset + offset)
+    }
+}
+
+fn to_storage_error<E: core::fmt::Debug>(e: E) -> StorageError {
+    use core::fmt::Write as _;
+    let mut text: String<32> = String::new();
+    let _ = write!(text, "{:?}", e);
+    StorageError::Other(text)
+}
+
+impl Storage for EspFlashStorage {
+    fn capacity(&self) -> u32 {
+        self.size
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), StorageError> {
+        let address = self.check(offset, buf.len())?;
+        with_flash(|flash| flash.read(address, buf).map_err(to_storage_error))
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), StorageError> {
+        let address = self.check(offset, data.len())?;
+        with_flash(|flash| NorFlash::write(flash, address, data).map_err(to_storage_error))
+    }
+
+    fn erase_sector(&mut self, offset: u32) -> Result<(), StorageError> {
+        let address = self.check(offset, SECTOR_SIZE as usize)?;
+        with_flash(|flash| {
+            flash
+                .erase(address, address + SECTOR_SIZE)
+                .map_err(to_storage_error)
+        })
+    }
+}
+
+#[embassy_executor::task]
+pub async fn odometer_task(store: OdometerStore<EspFlashStorage>) {
+    odometer_loop(store).await;
+}
+
+#[embassy_executor::task]
+pub async fn config_task(store: ConfigStore<EspFlashStorage>) {
+    config_loop(store).await;
+}